@@ -0,0 +1,47 @@
+/* eslint
+no-unused-vars: ["error", { "args": "none" }]
+class-methods-use-this: [
+  "error",{
+    "exceptMethods": ["worker", "builder"]
+  }
+]
+no-param-reassign: [
+  "error", {
+    "props": true,
+    "ignorePropertyModificationsFor": ["outputs"]
+  }
+]
+*/
+/* eslint-env es6 */
+
+import Rete from 'rete';
+import CustomNode from '@/components/CustomNode.vue';
+import { numSocket, jsonSocket, actionSocket } from '@/components/Sockets';
+import TextControl from '@/components/TextControl';
+import NumControl from '@/components/NumControl';
+
+class CallFlowComponent extends Rete.Component {
+  constructor() {
+    super('Call Flow');
+    this.data.Component = CustomNode;
+  }
+
+  builder(node) {
+    const actin = new Rete.Input('action', 'Action', actionSocket, true);
+    const inp = new Rete.Input('payload', 'JSON Payload', jsonSocket);
+    const out1 = new Rete.Output('payload', 'JSON Payload', jsonSocket);
+    const out2 = new Rete.Output('status', 'Status Code', numSocket);
+
+    return node
+      .addInput(actin)
+      .addInput(inp)
+      .addControl(new TextControl(this.editor, 'name'))
+      .addControl(new NumControl(this.editor, 'rev'))
+      .addOutput(out1)
+      .addOutput(out2);
+  }
+
+  worker(node, inputs, outputs) { }
+}
+
+export default CallFlowComponent;
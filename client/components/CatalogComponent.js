@@ -0,0 +1,68 @@
+/* eslint
+no-unused-vars: ["error", { "args": "none" }]
+class-methods-use-this: [
+  "error",{
+    "exceptMethods": ["worker", "builder"]
+  }
+]
+*/
+/* eslint-env es6 */
+
+import Rete from 'rete';
+import CustomNode from '@/components/CustomNode.vue';
+import {
+  numSocket, txtSocket, boolSocket, actionSocket, jsonSocket, anySocket, floatSocket, templateSocket,
+} from '@/components/Sockets';
+import NumControl from '@/components/NumControl';
+import TextControl from '@/components/TextControl';
+import JsonControl from '@/components/JsonControl';
+
+const sockets = {
+  Number: numSocket,
+  Float: floatSocket,
+  String: txtSocket,
+  Bool: boolSocket,
+  Json: jsonSocket,
+  Template: templateSocket,
+  Action: actionSocket,
+  ANY: anySocket,
+};
+
+// A node the server runs that has no hand-written component, built from its
+// entry in the `/nodes` catalog
+class CatalogComponent extends Rete.Component {
+  constructor(spec) {
+    super(spec.name);
+    this.spec = spec;
+    this.data.Component = CustomNode;
+  }
+
+  control(key, value) {
+    if (typeof value === 'number') {
+      return new NumControl(this.editor, key);
+    }
+    if (typeof value === 'string') {
+      return new TextControl(this.editor, key);
+    }
+    return new JsonControl(this.editor, key);
+  }
+
+  builder(node) {
+    const controls = new Map(this.spec.controls.map((c) => [c.key, c]));
+    this.spec.inputs.forEach((i) => {
+      const input = new Rete.Input(i.key, i.key, sockets[i.type] || anySocket, i.type === 'Action');
+      if (controls.has(i.key)) {
+        input.addControl(this.control(i.key, controls.get(i.key).default));
+        controls.delete(i.key);
+      }
+      node.addInput(input);
+    });
+    controls.forEach((c) => node.addControl(this.control(c.key, c.default)));
+    this.spec.outputs.forEach((o) => node.addOutput(new Rete.Output(o.key, o.key, sockets[o.type] || anySocket)));
+    return node;
+  }
+
+  worker(node, inputs, outputs) { }
+}
+
+export default CatalogComponent;
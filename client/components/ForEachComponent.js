@@ -0,0 +1,48 @@
+/* eslint
+no-unused-vars: ["error", { "args": "none" }]
+class-methods-use-this: [
+  "error",{
+    "exceptMethods": ["worker", "builder"]
+  }
+]
+no-param-reassign: [
+  "error", {
+    "props": true,
+    "ignorePropertyModificationsFor": ["outputs"]
+  }
+]
+*/
+/* eslint-env es6 */
+
+import Rete from 'rete';
+import CustomNode from '@/components/CustomNode.vue';
+import { jsonSocket, actionSocket } from '@/components/Sockets';
+import TextControl from '@/components/TextControl';
+import NumControl from '@/components/NumControl';
+import JsonControl from '@/components/JsonControl';
+
+// runs the graph in `flow`, or the stored flow named in `name`, once per element
+class ForEachComponent extends Rete.Component {
+  constructor() {
+    super('ForEach');
+    this.data.Component = CustomNode;
+  }
+
+  builder(node) {
+    const actin = new Rete.Input('action', 'Action', actionSocket, true);
+    const inp = new Rete.Input('payload', 'JSON Array', jsonSocket);
+    const out = new Rete.Output('json', 'JSON Results', jsonSocket);
+
+    return node
+      .addInput(actin)
+      .addInput(inp)
+      .addControl(new TextControl(this.editor, 'name'))
+      .addControl(new NumControl(this.editor, 'concurrency'))
+      .addControl(new JsonControl(this.editor, 'flow'))
+      .addOutput(out);
+  }
+
+  worker(node, inputs, outputs) { }
+}
+
+export default ForEachComponent;
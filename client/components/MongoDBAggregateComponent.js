@@ -0,0 +1,49 @@
+/* eslint
+no-unused-vars: ["error", { "args": "none" }]
+class-methods-use-this: [
+  "error",{
+    "exceptMethods": ["worker", "builder"]
+  }
+]
+no-param-reassign: [
+  "error", {
+    "props": true,
+    "ignorePropertyModificationsFor": ["outputs"]
+  }
+]
+*/
+/* eslint-env es6 */
+
+import Rete from 'rete';
+import CustomNode from '@/components/CustomNode.vue';
+import { txtSocket, jsonSocket, actionSocket } from '@/components/Sockets';
+
+class MongoDBAggregateComponent extends Rete.Component {
+  constructor() {
+    super('MongoDB Aggregate');
+    this.data.Component = CustomNode;
+    this.data.dbname = 'flows';
+    this.data.colname = 'cache';
+  }
+
+  builder(node) {
+    const actin = new Rete.Input('action', 'Action', actionSocket, true);
+    const inp1 = new Rete.Input('dbname', 'Database (Empty for default)', txtSocket);
+    const inp2 = new Rete.Input('colname', 'Collection', txtSocket);
+    const inp3 = new Rete.Input('pipeline', 'Pipeline', jsonSocket);
+    const out = new Rete.Output('json', 'Aggregate Result', jsonSocket);
+
+    return node
+      .addInput(actin)
+      .addInput(inp1)
+      .addInput(inp2)
+      .addInput(inp3)
+      .addOutput(out);
+  }
+
+  worker(node, inputs, outputs) {
+
+  }
+}
+
+export default MongoDBAggregateComponent;
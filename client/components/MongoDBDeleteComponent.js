@@ -0,0 +1,49 @@
+/* eslint
+no-unused-vars: ["error", { "args": "none" }]
+class-methods-use-this: [
+  "error",{
+    "exceptMethods": ["worker", "builder"]
+  }
+]
+no-param-reassign: [
+  "error", {
+    "props": true,
+    "ignorePropertyModificationsFor": ["outputs"]
+  }
+]
+*/
+/* eslint-env es6 */
+
+import Rete from 'rete';
+import CustomNode from '@/components/CustomNode.vue';
+import { txtSocket, numSocket, actionSocket } from '@/components/Sockets';
+
+class MongoDBDeleteComponent extends Rete.Component {
+  constructor() {
+    super('MongoDB Delete');
+    this.data.Component = CustomNode;
+    this.data.query = 'deleted == false';
+    this.data.colname = 'cache';
+    this.data.many = false;
+    this.data.hard = false;
+  }
+
+  builder(node) {
+    const actin = new Rete.Input('action', 'Action', actionSocket, true);
+    const inp2 = new Rete.Input('colname', 'Collection Name', txtSocket);
+    const inp3 = new Rete.Input('query', 'Query String', txtSocket);
+    const out = new Rete.Output('count', 'Deleted Count', numSocket);
+
+    return node
+      .addInput(actin)
+      .addInput(inp2)
+      .addInput(inp3)
+      .addOutput(out);
+  }
+
+  worker(node, inputs, outputs) {
+
+  }
+}
+
+export default MongoDBDeleteComponent;
@@ -21,6 +21,7 @@ import {
   numSocket,
   jsonSocket,
   actionSocket,
+  anySocket,
 } from '@/components/Sockets';
 
 class MongoDBGetComponent extends Rete.Component {
@@ -41,7 +42,11 @@ class MongoDBGetComponent extends Rete.Component {
     const inp2 = new Rete.Input('colname', 'Collection', txtSocket);
     const inp3 = new Rete.Input('query', 'Query String', txtSocket);
     const inp4 = new Rete.Input('limit', 'Limit', numSocket);
+    const inp5 = new Rete.Input('skip', 'Skip', numSocket);
+    const inp6 = new Rete.Input('sort', 'Sort', anySocket);
+    const inp7 = new Rete.Input('projection', 'Projection', jsonSocket);
     const out = new Rete.Output('json', 'Query Result', jsonSocket);
+    const count = new Rete.Output('count', 'Total Count', numSocket);
 
     return node
       .addInput(actin)
@@ -50,7 +55,11 @@ class MongoDBGetComponent extends Rete.Component {
       .addInput(inp2)
       .addInput(inp3)
       .addInput(inp4)
-      .addOutput(out);
+      .addInput(inp5)
+      .addInput(inp6)
+      .addInput(inp7)
+      .addOutput(out)
+      .addOutput(count);
   }
 
   worker(node, inputs, outputs) {
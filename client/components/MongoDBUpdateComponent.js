@@ -16,7 +16,12 @@ no-param-reassign: [
 
 import Rete from 'rete';
 import CustomNode from '@/components/CustomNode.vue';
-import { txtSocket, actionSocket, jsonSocket } from '@/components/Sockets';
+import {
+  txtSocket,
+  numSocket,
+  actionSocket,
+  jsonSocket,
+} from '@/components/Sockets';
 
 class MongoDBUpdateComponent extends Rete.Component {
   constructor() {
@@ -26,6 +31,8 @@ class MongoDBUpdateComponent extends Rete.Component {
     this.data.query = 'deleted == false';
     this.data.dbname = 'flows';
     this.data.colname = 'cache';
+    this.data.many = false;
+    this.data.upsert = false;
   }
 
   builder(node) {
@@ -35,6 +42,8 @@ class MongoDBUpdateComponent extends Rete.Component {
     const inp3 = new Rete.Input('query', 'Query String', txtSocket);
     const inp4 = new Rete.Input('payload', 'JSON Payload', jsonSocket);
     const out = new Rete.Output('json', 'Query Result', jsonSocket);
+    const matched = new Rete.Output('matched', 'Matched', numSocket);
+    const modified = new Rete.Output('modified', 'Modified', numSocket);
 
     return node
       .addInput(actin)
@@ -42,7 +51,9 @@ class MongoDBUpdateComponent extends Rete.Component {
       .addInput(inp2)
       .addInput(inp3)
       .addInput(inp4)
-      .addOutput(out);
+      .addOutput(out)
+      .addOutput(matched)
+      .addOutput(modified);
   }
 
   worker(node, inputs, outputs) {
@@ -16,7 +16,7 @@ no-param-reassign: [
 
 import Rete from 'rete';
 import CustomNode from '@/components/CustomNode.vue';
-import { numSocket, jsonSocket } from '@/components/Sockets';
+import { numSocket, jsonSocket, actionSocket } from '@/components/Sockets';
 import NumControl from '@/components/NumControl';
 
 class OutputComponent extends Rete.Component {
@@ -27,7 +27,7 @@ class OutputComponent extends Rete.Component {
   }
 
   builder(node) {
-    // const actin = new Rete.Input('action', 'Action', actionSocket, true);
+    const actin = new Rete.Input('action', 'Action', actionSocket, true);
 
     const inp1 = new Rete.Input('payload', 'REST Payload', jsonSocket);
     const inp2 = new Rete.Input('status', 'Status Code', numSocket);
@@ -35,7 +35,7 @@ class OutputComponent extends Rete.Component {
     inp2.addControl(new NumControl(this.editor, 'status'));
 
     return node
-      // .addInput(actin)
+      .addInput(actin)
       .addInput(inp1)
       .addInput(inp2);
   }
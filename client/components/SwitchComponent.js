@@ -0,0 +1,72 @@
+/* eslint
+no-unused-vars: ["error", { "args": "none" }]
+class-methods-use-this: [
+  "error",{
+    "exceptMethods": ["builder"]
+  }
+]
+no-param-reassign: [
+  "error", {
+    "props": true,
+    "ignorePropertyModificationsFor": ["outputs"]
+  }
+]
+*/
+/* eslint-env es6 */
+
+import Rete from 'rete';
+import CustomNode from '@/components/CustomNode.vue';
+import { anySocket, actionSocket } from '@/components/Sockets';
+import TextControl from '@/components/TextControl';
+import JsonControl from '@/components/JsonControl';
+
+// cases are kept in node data as [{ "name": "order", "value": "order" }],
+// each one gets its own action output next to `default`
+function caseNames(cases) {
+  return (Array.isArray(cases) ? cases : [])
+    .map((c) => c && c.name)
+    .filter((name) => name && name !== 'default');
+}
+
+class SwitchComponent extends Rete.Component {
+  constructor() {
+    super('Switch');
+    this.data.Component = CustomNode;
+  }
+
+  builder(node) {
+    const actin = new Rete.Input('action', 'Action', actionSocket, true);
+    const inp = new Rete.Input('value', 'Value', anySocket);
+
+    node
+      .addInput(actin)
+      .addInput(inp)
+      .addControl(new TextControl(this.editor, 'field'))
+      .addControl(new JsonControl(this.editor, 'cases'));
+    caseNames(node.data.cases).forEach((name) => {
+      node.addOutput(new Rete.Output(name, name, actionSocket));
+    });
+    return node.addOutput(new Rete.Output('default', 'Default', actionSocket));
+  }
+
+  // keeps the outputs in line with the cases while they are edited
+  worker(node, inputs, outputs) {
+    const editorNode = this.editor.nodes.find((n) => n.id === node.id);
+    if (!editorNode) {
+      return;
+    }
+    const names = caseNames(node.data.cases);
+    Array.from(editorNode.outputs.values())
+      .filter((o) => o.key !== 'default' && !names.includes(o.key))
+      .forEach((o) => {
+        o.connections.slice().forEach((c) => this.editor.removeConnection(c));
+        editorNode.removeOutput(o);
+      });
+    names
+      .filter((name) => !editorNode.outputs.has(name))
+      .forEach((name) => editorNode.addOutput(new Rete.Output(name, name, actionSocket)));
+    editorNode.update();
+  }
+}
+
+export default SwitchComponent;
@@ -0,0 +1,39 @@
+/* eslint
+no-unused-vars: ["error", { "args": "none" }]
+class-methods-use-this: [
+  "error",{
+    "exceptMethods": ["worker", "builder"]
+  }
+]
+*/
+/* eslint-env es6 */
+
+import Rete from 'rete';
+import CustomNode from '@/components/CustomNode.vue';
+import { jsonSocket, actionSocket } from '@/components/Sockets';
+
+class TryComponent extends Rete.Component {
+  constructor() {
+    super('Try');
+    this.data.Component = CustomNode;
+  }
+
+  builder(node) {
+    const actin = new Rete.Input('action', 'Action', actionSocket, true);
+    const inp = new Rete.Input('payload', 'Payload', jsonSocket);
+    const otry = new Rete.Output('try', 'Try', actionSocket);
+    const oerror = new Rete.Output('error', 'Error', jsonSocket);
+    const out = new Rete.Output('payload', 'Payload', jsonSocket);
+
+    return node
+      .addInput(actin)
+      .addInput(inp)
+      .addOutput(otry)
+      .addOutput(oerror)
+      .addOutput(out);
+  }
+
+  worker(node, inputs, outputs) { }
+}
+
+export default TryComponent;
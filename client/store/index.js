@@ -61,6 +61,20 @@ export default new Vuex.Store({
         withCredentials: true,
       });
     },
+    async getNodes(_context) {
+      const res = await axios.get(`${process.env.VUE_APP_API_URL}/nodes`, {
+        withCredentials: true,
+      });
+      return res.data;
+    },
+    async validateFlow(_context, { flow }) {
+      const res = await axios.post(`${process.env.VUE_APP_API_URL}/flows/validate`, {
+        flow,
+      }, {
+        withCredentials: true,
+      });
+      return res.data;
+    },
     async testFlow(_context, { payload, flow }) {
       const res = await axios.post(`${process.env.VUE_APP_API_URL}/flowtest`, {
         payload,
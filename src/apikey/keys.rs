@@ -0,0 +1,194 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use jsonwebtoken::jwk::{AlgorithmParameters, JwkSet};
+use once_cell::sync::OnceCell;
+
+use crate::apikey::{ApiKey, ApiKeyError};
+
+static KEYS: OnceCell<KeyStore> = OnceCell::new();
+
+#[derive(Debug)]
+pub enum KeyError {
+  Missing,
+  UnsupportedAlgorithm(String),
+  Io(std::io::Error),
+  Jwt(jsonwebtoken::errors::Error),
+  Jwks(serde_json::Error),
+}
+
+impl From<std::io::Error> for KeyError {
+  fn from(e: std::io::Error) -> Self {
+    KeyError::Io(e)
+  }
+}
+
+impl From<jsonwebtoken::errors::Error> for KeyError {
+  fn from(e: jsonwebtoken::errors::Error) -> Self {
+    KeyError::Jwt(e)
+  }
+}
+
+impl From<serde_json::Error> for KeyError {
+  fn from(e: serde_json::Error) -> Self {
+    KeyError::Jwks(e)
+  }
+}
+
+/// A JWKS file on disk, reloaded whenever its modification time changes so
+/// keys can be rotated without a restart.
+pub struct JwksFile {
+  path: PathBuf,
+  keys: RwLock<(Option<SystemTime>, JwkSet)>,
+}
+
+impl JwksFile {
+  pub fn load(path: PathBuf) -> Result<Self, KeyError> {
+    let modified = fs::metadata(&path)?.modified().ok();
+    let set: JwkSet = serde_json::from_slice(&fs::read(&path)?)?;
+    Ok(JwksFile { path, keys: RwLock::new((modified, set)) })
+  }
+
+  fn refresh(&self) {
+    let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+    let stale = self.keys.read().map(|k| k.0 != modified).unwrap_or(false);
+    if !stale {
+      return;
+    }
+    match fs::read(&self.path).map_err(KeyError::from).and_then(|b| Ok(serde_json::from_slice::<JwkSet>(&b)?)) {
+      Ok(set) => if let Ok(mut keys) = self.keys.write() {
+        *keys = (modified, set);
+      },
+      // keep verifying with the keys we have until the file is fixed
+      Err(e) => println!("could not reload {}: {:?}", self.path.display(), e),
+    }
+  }
+
+  fn key(&self, kid: Option<&str>, alg: Algorithm) -> Option<DecodingKey> {
+    self.refresh();
+    let keys = self.keys.read().ok()?;
+    keys.1.keys.iter()
+      .filter(|k| kid.is_none() || k.common.key_id.as_deref() == kid)
+      .filter(|k| k.common.algorithm.map(|a| a == alg).unwrap_or(true))
+      .find(|k| matches!((&k.algorithm, alg),
+        (AlgorithmParameters::RSA(_), Algorithm::RS256) | (AlgorithmParameters::EllipticCurve(_), Algorithm::ES256)))
+      .and_then(|k| DecodingKey::from_jwk(k).ok())
+  }
+}
+
+pub enum KeySource {
+  Secret(DecodingKey),
+  PublicKey(Algorithm, DecodingKey),
+  Jwks(JwksFile),
+}
+
+/// How api keys are verified. Configured from the environment:
+///
+/// - `JWT_JWKS` path to a JWKS file with RS256 / ES256 keys, or
+/// - `JWT_PUBLIC_KEY` path to a PEM public key with `JWT_ALGORITHM` `RS256` (default) or `ES256`, or
+/// - `JWT_SECRET` for HS256 signed keys
+///
+/// `JWT_ISSUER` and `JWT_AUDIENCE` (comma separated) restrict the accepted `iss` and `aud` claims.
+pub struct KeyStore {
+  source: KeySource,
+  issuers: Vec<String>,
+  audiences: Vec<String>,
+}
+
+impl KeyStore {
+  pub fn from_env() -> Result<Self, KeyError> {
+    let source = if let Ok(path) = env::var("JWT_JWKS") {
+      KeySource::Jwks(JwksFile::load(PathBuf::from(path))?)
+    } else if let Ok(path) = env::var("JWT_PUBLIC_KEY") {
+      let pem = fs::read(path)?;
+      match env::var("JWT_ALGORITHM").unwrap_or_else(|_| "RS256".to_string()).as_str() {
+        "RS256" => KeySource::PublicKey(Algorithm::RS256, DecodingKey::from_rsa_pem(&pem)?),
+        "ES256" => KeySource::PublicKey(Algorithm::ES256, DecodingKey::from_ec_pem(&pem)?),
+        other => return Err(KeyError::UnsupportedAlgorithm(other.to_string())),
+      }
+    } else if let Ok(secret) = env::var("JWT_SECRET") {
+      KeySource::Secret(DecodingKey::from_secret(secret.as_bytes()))
+    } else {
+      return Err(KeyError::Missing);
+    };
+    Ok(KeyStore { source, issuers: list_var("JWT_ISSUER"), audiences: list_var("JWT_AUDIENCE") })
+  }
+
+  pub fn verify(&self, token: &str) -> Result<ApiKey, ApiKeyError> {
+    let header = decode_header(token).map_err(|_| ApiKeyError::Invalid)?;
+    let (alg, key) = match &self.source {
+      KeySource::Secret(key) => (Algorithm::HS256, key.clone()),
+      KeySource::PublicKey(alg, key) => (*alg, key.clone()),
+      KeySource::Jwks(jwks) => match header.alg {
+        Algorithm::RS256 | Algorithm::ES256 => (header.alg, jwks.key(header.kid.as_deref(), header.alg).ok_or(ApiKeyError::Invalid)?),
+        _ => return Err(ApiKeyError::Invalid),
+      },
+    };
+    if header.alg != alg {
+      return Err(ApiKeyError::Invalid);
+    }
+    decode::<ApiKey>(token, &key, &self.validation(alg))
+      .map(|data| data.claims)
+      .map_err(|_| ApiKeyError::Invalid)
+  }
+
+  fn validation(&self, alg: Algorithm) -> Validation {
+    let mut validation = Validation::new(alg);
+    // exp is in milliseconds on our keys and in seconds on others, it is checked after decoding
+    validation.validate_exp = false;
+    validation.required_spec_claims = HashSet::new();
+    if !self.issuers.is_empty() {
+      validation.set_issuer(&self.issuers);
+      validation.required_spec_claims.insert("iss".to_string());
+    }
+    if !self.audiences.is_empty() {
+      validation.set_audience(&self.audiences);
+      validation.required_spec_claims.insert("aud".to_string());
+    }
+    validation
+  }
+}
+
+fn list_var(name: &str) -> Vec<String> {
+  env::var(name)
+    .map(|v| v.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+    .unwrap_or_default()
+}
+
+/// Loads the key material, the server refuses to start without it
+pub fn init() -> Result<(), KeyError> {
+  let _ = KEYS.set(KeyStore::from_env()?);
+  Ok(())
+}
+
+pub fn verify(token: &str) -> Result<ApiKey, ApiKeyError> {
+  KEYS.get().ok_or(ApiKeyError::Invalid)?.verify(token)
+}
+
+#[cfg(test)]
+mod keys_test {
+  use jsonwebtoken::{encode, DecodingKey, EncodingKey, Header};
+  use crate::apikey::keys::{KeySource, KeyStore};
+
+  fn token(iss: &str, aud: &str) -> String {
+    let claims = json!({ "iss": iss, "aud": aud, "iat": 0, "jti": "1", "sub": "alice", "legacy": false, "scopes": [], "entities": [] });
+    encode(&Header::default(), &claims, &EncodingKey::from_secret(b"secret")).unwrap()
+  }
+
+  #[test]
+  fn validates_claims() {
+    let store = KeyStore {
+      source: KeySource::Secret(DecodingKey::from_secret(b"secret")),
+      issuers: vec!["auth".to_string()],
+      audiences: vec!["flows".to_string()],
+    };
+    assert_eq!(store.verify(&token("auth", "flows")).unwrap().sub, "alice");
+    assert!(store.verify(&token("other", "flows")).is_err());
+    assert!(store.verify(&token("auth", "rules")).is_err());
+  }
+}
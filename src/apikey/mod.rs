@@ -2,26 +2,25 @@ use rocket::Outcome;
 use rocket::http::Status;
 use rocket::request::{self, Request, FromRequest};
 use std::env;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use funlib::Foldable::*;
 
-use hmac::{Hmac, NewMac};
-use jwt::VerifyWithKey;
-use sha2::Sha256;
+pub mod keys;
+pub mod revocation;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Scope {
   pub scope: String,
   pub actions: Vec<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Partners {
   pub demand: String,
   pub channel: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct ApiKey {
   pub iss: String,
   pub iat: u64,
@@ -42,7 +41,7 @@ pub enum ApiKeyError {
   Invalid,
   Expired,
   BadCount,
-  InvalidKeyLength,
+  Revoked,
   SysTimeError(std::time::SystemTimeError),
 }
 
@@ -52,31 +51,37 @@ impl From<std::time::SystemTimeError> for ApiKeyError {
   }
 }
 
-impl From<hmac::crypto_mac::InvalidKeyLength> for ApiKeyError {
-  fn from(_: hmac::crypto_mac::InvalidKeyLength) -> Self {
-    ApiKeyError::InvalidKeyLength
+pub fn get_apikey_without_bearer(token: &str) -> Result<ApiKey, ApiKeyError> {
+  let claims = keys::verify(token)?;
+  let now = SystemTime::now();
+  let in_ms: u128 = now.duration_since(UNIX_EPOCH)?.as_millis();
+  if expired(&claims, in_ms) {
+    Err(ApiKeyError::Expired)
+  } else if revocation::is_revoked(&claims) {
+    Err(ApiKeyError::Revoked)
+  } else {
+    Ok(claims)
   }
 }
 
-pub fn get_apikey_without_bearer(token: &str) -> Result<ApiKey, ApiKeyError> {
-  let skey: Hmac<Sha256> = match env::var("JWT_SECRET") {
-    Ok(val) => Hmac::new_varkey(val.as_bytes())?,
-    Err(_) => Hmac::new_varkey(b"e72e15b5-04f5-4818-8e8e-88ea69a4817c")?,
-  };
-  match VerifyWithKey::<ApiKey>::verify_with_key(token, &skey) {
-    Ok(claims) => {
-      let now = SystemTime::now();
-      let in_ms: u128 = now.duration_since(UNIX_EPOCH)?.as_millis();
-      if claims.exp == None || claims.exp.any(|&ts| u128::from(ts) > in_ms) {
-        Ok(claims)
-      } else {
-        Err(ApiKeyError::Expired)
-      }
-    },
-    Err(_) => Err(ApiKeyError::Invalid)
+fn expired(claims: &ApiKey, in_ms: u128) -> bool {
+  match (claims.exp, max_token_age()) {
+    (Some(ts), _) => millis(ts) <= in_ms,
+    (None, Some(max_age)) => millis(claims.iat) + max_age.as_millis() <= in_ms,
+    (None, None) => false,
   }
 }
 
+/// Keys without `exp` are only accepted for `MAX_TOKEN_AGE_SECS` after `iat`
+fn max_token_age() -> Option<Duration> {
+  env::var("MAX_TOKEN_AGE_SECS").ok().and_then(|s| s.parse().ok()).map(Duration::from_secs)
+}
+
+/// `iat` and `exp` are issued in seconds by standard issuers and in milliseconds by ours
+fn millis(ts: u64) -> u128 {
+  if ts < 100_000_000_000 { u128::from(ts) * 1000 } else { u128::from(ts) }
+}
+
 pub fn get_apikey(token: &str) -> Result<ApiKey, ApiKeyError> {
   if token.starts_with("Bearer ") || token.starts_with("bearer ") {
     let nt = token.replace("Bearer ", "").replace("bearer ", "");
@@ -105,4 +110,23 @@ impl<'a, 'r> FromRequest<'a, 'r> for ApiKey {
       _ => Outcome::Failure((Status::Unauthorized, ApiKeyError::BadCount)),
     }
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod apikey_test {
+  use crate::apikey::{expired, ApiKey};
+  use crate::util::test::apikey;
+
+  fn claims(exp: u64) -> ApiKey {
+    apikey("alice", json!({ "exp": exp }))
+  }
+
+  #[test]
+  fn reads_exp_in_seconds_and_millis() {
+    let now_ms: u128 = 1_700_000_000_000;
+    assert!(!expired(&claims(1_700_000_060), now_ms));
+    assert!(expired(&claims(1_699_999_940), now_ms));
+    assert!(!expired(&claims(1_700_000_060_000), now_ms));
+    assert!(expired(&claims(1_699_999_940_000), now_ms));
+  }
+}
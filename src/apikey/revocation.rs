@@ -0,0 +1,132 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use bson::Document;
+use mongodb::sync::{Client, Collection};
+use once_cell::sync::OnceCell;
+use rocket::State;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket_contrib::json::{Json, JsonValue};
+use serde_json::Value;
+
+use crate::apikey::{check_access, millis, ApiKey};
+use crate::nocode::error::{ApiError, ErrorKind};
+use crate::util::{bson_or_null, current_millis, unauthorized};
+
+static REVOCATIONS: OnceCell<Revocations> = OnceCell::new();
+
+#[derive(Default)]
+struct Revoked {
+  loaded: Option<Instant>,
+  jtis: HashSet<String>,
+  /// keys of a subject issued before this time (ms) are revoked
+  subs: HashMap<String, i64>,
+}
+
+/// Revoked api keys, kept in the `revocations` collection and cached in memory.
+/// The cache is reloaded every `REVOCATION_REFRESH_SECS` (default 30) so
+/// revocations made on other instances are picked up.
+pub struct Revocations {
+  coll: Collection,
+  refresh: Duration,
+  revoked: RwLock<Revoked>,
+}
+
+impl Revocations {
+  pub fn new(conn: &Client) -> Self {
+    let secs = env::var("REVOCATION_REFRESH_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(30);
+    Revocations {
+      coll: conn.database("flows").collection("revocations"),
+      refresh: Duration::from_secs(secs),
+      revoked: RwLock::new(Revoked::default()),
+    }
+  }
+
+  fn reload(&self) -> Result<(), mongodb::error::Error> {
+    let mut revoked = Revoked { loaded: Some(Instant::now()), ..Revoked::default() };
+    for doc in self.coll.find(None, None)? {
+      let doc = doc?;
+      if let Ok(jti) = doc.get_str("jti") {
+        revoked.jtis.insert(jti.to_string());
+      }
+      if let (Ok(sub), Ok(at)) = (doc.get_str("sub"), doc.get_i64("revoked_at")) {
+        let entry = revoked.subs.entry(sub.to_string()).or_insert(at);
+        *entry = (*entry).max(at);
+      }
+    }
+    if let Ok(mut current) = self.revoked.write() {
+      *current = revoked;
+    }
+    Ok(())
+  }
+
+  fn refresh_if_stale(&self) {
+    let stale = self.revoked.read()
+      .map(|r| r.loaded.map(|l| l.elapsed() >= self.refresh).unwrap_or(true))
+      .unwrap_or(true);
+    if stale {
+      // on failure keep checking against the last list we loaded
+      if let Err(e) = self.reload() {
+        println!("could not reload revocations: {}", e);
+      }
+    }
+  }
+
+  pub fn is_revoked(&self, apikey: &ApiKey) -> bool {
+    self.refresh_if_stale();
+    match self.revoked.read() {
+      Ok(revoked) => revoked.jtis.contains(&apikey.jti) || revoked.subs.get(&apikey.sub)
+        .map(|at| (millis(apikey.iat) as i64) <= *at)
+        .unwrap_or(false),
+      Err(_) => true,
+    }
+  }
+
+  fn revoke(&self, entry: Document) -> Result<(), mongodb::error::Error> {
+    self.coll.insert_one(entry, None)?;
+    self.reload()
+  }
+}
+
+pub fn init(conn: &Client) {
+  let _ = REVOCATIONS.set(Revocations::new(conn));
+}
+
+pub fn is_revoked(apikey: &ApiKey) -> bool {
+  REVOCATIONS.get().map(|r| r.is_revoked(apikey)).unwrap_or(false)
+}
+
+/// Revokes a single key by `jti`, or every key issued to `sub` so far
+#[post("/apikeys/revoke", format="application/json", data="<data>")]
+pub fn revoke(data: Json<Value>, apikey: ApiKey) -> anyhow::Result<status::Custom<JsonValue>> {
+  if !check_access(&apikey, "apikeys", "revoke") {
+    return Ok(unauthorized());
+  }
+  let revocations = match REVOCATIONS.get() {
+    Some(r) => r,
+    None => return Ok(ApiError::new(ErrorKind::Unavailable, "revocations are not available").into()),
+  };
+  let reason = bson_or_null(data.0["reason"].as_str());
+  let now = current_millis()?;
+  let entry = match (data.0["jti"].as_str(), data.0["sub"].as_str()) {
+    (Some(jti), None) => doc!("jti": jti, "revoked_at": now, "revoked_by": apikey.sub.clone(), "reason": reason),
+    (None, Some(sub)) => doc!("sub": sub, "revoked_at": now, "revoked_by": apikey.sub.clone(), "reason": reason),
+    _ => return Ok(ApiError::new(ErrorKind::BadRequest, "either jti or sub is required").into()),
+  };
+  revocations.revoke(entry.clone())?;
+  let entry: Value = bson::Bson::Document(entry).into_relaxed_extjson();
+  Ok(status::Custom(Status::Ok, entry.into()))
+}
+
+#[get("/apikeys/revocations")]
+pub fn get_revocations(apikey: ApiKey, conn: State<Client>) -> anyhow::Result<status::Custom<JsonValue>> {
+  if !check_access(&apikey, "apikeys", "revoke") {
+    return Ok(unauthorized());
+  }
+  let cursor = conn.database("flows").collection("revocations").find(None, None)?;
+  let vec: Vec<Value> = to_vec!(cursor);
+  Ok(status::Custom(Status::Ok, json!(vec).into()))
+}
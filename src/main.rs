@@ -8,7 +8,8 @@ extern crate rocket_contrib;
 extern crate querylib;
 #[macro_use] extern crate serde_json;
 extern crate rocket_cors;
-extern crate jwt;
+extern crate jsonwebtoken;
+extern crate once_cell;
 extern crate funlib;
 extern crate crypto;
 extern crate uuid;
@@ -22,7 +23,9 @@ extern crate js_sandbox;
 
 
 #[macro_use] mod util;
+mod apikey;
 mod nocode;
+mod rules;
 
 use rocket::response::NamedFile;
 use rocket::http::Status;
@@ -38,6 +41,13 @@ enum MainError {
   MongoError(mongodb::error::Error),
   RocketCorsError(rocket_cors::Error),
   SerdeJsonError(serde_json::error::Error),
+  KeyError(apikey::keys::KeyError),
+}
+
+impl From<apikey::keys::KeyError> for MainError {
+  fn from(e: apikey::keys::KeyError) -> Self {
+    MainError::KeyError(e)
+  }
 }
 
 impl From<serde_json::error::Error> for MainError {
@@ -90,6 +100,7 @@ fn img_files(file: PathBuf) -> Option<NamedFile> {
 
 #[tokio::main]
 async fn main() -> std::result::Result<(), MainError> {
+  apikey::keys::init()?;
 
   let conn_string = match env::var("DB_CONN_STRING") {
     Ok(cs) => cs,
@@ -100,12 +111,16 @@ async fn main() -> std::result::Result<(), MainError> {
 
   client_options.app_name = Some("flows".to_string());
   let client = Client::with_options(client_options)?;
+  nocode::ensure_indexes(&client)?;
+  apikey::revocation::init(&client);
 
   let cors: Cors = match env::var("CORS_JSON") {
     Err(_) => CorsOptions::default(),
     Ok(cors) => serde_json::from_str(&cors)?,
   }.to_cors()?;
 
+  let policy = nocode::policy::MongoPolicy::from_env()?;
+
   rocket::ignite()
     .mount("/", routes![
       healthcheck,
@@ -115,6 +130,31 @@ async fn main() -> std::result::Result<(), MainError> {
       nocode::run_flow,
       nocode::test_flow,
       nocode::set_active,
+      nocode::delete_flow,
+      nocode::rename_flow,
+      nocode::clone_flow,
+      nocode::get_revisions,
+      nocode::diff_revisions,
+      nocode::get_acl,
+      nocode::set_acl,
+      nocode::get_nodes,
+      nocode::validate_flow,
+      rules::save_rule,
+      rules::get_rules,
+      rules::get_rule,
+      rules::run_rule,
+      rules::test_rule,
+      rules::set_active,
+      rules::delete_rule,
+      rules::rename_rule,
+      rules::clone_rule,
+      rules::get_revisions,
+      rules::diff_revisions,
+      rules::get_acl,
+      rules::set_acl,
+      rules::validate_rule,
+      apikey::revocation::revoke,
+      apikey::revocation::get_revocations,
       index,
       edit,
       js_files,
@@ -124,6 +164,7 @@ async fn main() -> std::result::Result<(), MainError> {
     .attach(cors)
     .register(catchers![
       util::not_found_catcher,
-    ]).manage(client).launch();
+      util::unauthorized_catcher,
+    ]).manage(client).manage(policy).launch();
     Ok(())
 }
\ No newline at end of file
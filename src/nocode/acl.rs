@@ -0,0 +1,142 @@
+use bson::{Bson, Document};
+use serde_json::Value;
+
+use crate::apikey::{check_access, ApiKey};
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Role {
+  Runner,
+  Editor,
+  Owner,
+}
+
+/// Who may do what with a single flow. Each entry is matched against the
+/// `sub`, `email` or any of the `entities` of the caller's api key.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Acl {
+  #[serde(default)]
+  pub owners: Vec<String>,
+  #[serde(default)]
+  pub editors: Vec<String>,
+  #[serde(default)]
+  pub runners: Vec<String>,
+}
+
+impl Acl {
+  pub fn owned_by(apikey: &ApiKey) -> Self {
+    Acl { owners: vec![apikey.sub.clone()], ..Acl::default() }
+  }
+
+  /// The acl of a meta document, `None` for flows saved before ACLs existed.
+  /// One that can't be read lists nobody, so it only lets admins in.
+  pub fn from_value(meta: &Value) -> Option<Self> {
+    match meta.get("acl") {
+      None | Some(Value::Null) => None,
+      Some(acl) => Some(serde_json::from_value(acl.clone()).unwrap_or_default()),
+    }
+  }
+
+  pub fn from_document(meta: &Document) -> Option<Self> {
+    match meta.get("acl") {
+      None | Some(Bson::Null) => None,
+      Some(acl) => Some(bson::from_bson(acl.clone()).unwrap_or_default()),
+    }
+  }
+
+  pub fn to_document(&self) -> Document {
+    match bson::to_bson(self) {
+      Ok(Bson::Document(d)) => d,
+      _ => Document::new(),
+    }
+  }
+
+  pub fn role(&self, apikey: &ApiKey) -> Option<Role> {
+    let matches = |p: &String| *p == apikey.sub || apikey.email.as_ref() == Some(p) || apikey.entities.contains(p);
+    if self.owners.iter().any(matches) {
+      Some(Role::Owner)
+    } else if self.editors.iter().any(matches) {
+      Some(Role::Editor)
+    } else if self.runners.iter().any(matches) {
+      Some(Role::Runner)
+    } else {
+      None
+    }
+  }
+}
+
+/// Flows saved before ACLs existed have none and stay open to every caller with
+/// the right scope, but only callers holding the `admin` action on `scope` may do
+/// what takes an owner with them, like claiming them. Admins pass any acl.
+pub fn allowed(scope: &str, acl: Option<Acl>, apikey: &ApiKey, needed: Role) -> bool {
+  check_access(apikey, scope, "admin") || match acl {
+    None => needed < Role::Owner,
+    Some(acl) => acl.role(apikey).map(|r| r >= needed).unwrap_or(false),
+  }
+}
+
+/// The query on meta documents matching what `allowed` lets through
+pub fn filter(scope: &str, apikey: &ApiKey, needed: Role) -> Document {
+  if check_access(apikey, scope, "admin") {
+    return Document::new();
+  }
+  let ids: Vec<String> = std::iter::once(apikey.sub.clone())
+    .chain(apikey.email.clone())
+    .chain(apikey.entities.iter().cloned())
+    .collect();
+  let roles = [(Role::Owner, "acl.owners"), (Role::Editor, "acl.editors"), (Role::Runner, "acl.runners")];
+  let mut any: Vec<Document> = vec![];
+  if needed < Role::Owner {
+    any.push(doc!("acl": Bson::Null));
+  }
+  for (_, field) in roles.iter().filter(|(role, _)| *role >= needed) {
+    let mut listed = Document::new();
+    listed.insert(*field, doc!("$in": ids.clone()));
+    any.push(listed);
+  }
+  doc!("$or": any)
+}
+
+#[cfg(test)]
+mod acl_test {
+  use crate::apikey::ApiKey;
+  use crate::nocode::acl::{allowed, filter, Acl, Role};
+  use crate::util::test;
+
+  fn apikey(sub: &str, entities: Vec<&str>) -> ApiKey {
+    test::apikey(sub, json!({ "entities": entities, "email": format!("{}@example.com", sub) }))
+  }
+
+  #[test]
+  fn roles() {
+    let acl = Acl { owners: vec!["alice".into()], editors: vec!["team-a".into()], runners: vec!["bob@example.com".into()] };
+    assert_eq!(acl.role(&apikey("alice", vec![])), Some(Role::Owner));
+    assert_eq!(acl.role(&apikey("carol", vec!["team-a"])), Some(Role::Editor));
+    assert_eq!(acl.role(&apikey("bob", vec![])), Some(Role::Runner));
+    assert!(!allowed("flows", Some(acl.clone()), &apikey("bob", vec![]), Role::Editor));
+    assert!(!allowed("flows", Some(acl), &apikey("dave", vec!["team-b"]), Role::Runner));
+    assert!(allowed("flows", None, &apikey("dave", vec![]), Role::Editor));
+    assert!(!allowed("flows", None, &apikey("dave", vec![]), Role::Owner));
+  }
+
+  #[test]
+  fn denies_malformed() {
+    let meta = doc!{"name": "a", "acl": {"owners": "alice"}};
+    let acl = Acl::from_document(&meta);
+    assert!(acl.is_some());
+    assert!(!allowed("flows", acl, &apikey("alice", vec![]), Role::Runner));
+    assert!(Acl::from_document(&doc!{"name": "a", "acl": null}).is_none());
+    assert!(Acl::from_value(&json!({"name": "a"})).is_none());
+    assert!(Acl::from_value(&json!({"name": "a", "acl": 1})).is_some());
+  }
+
+  #[test]
+  fn filters_in_query() {
+    let query = filter("flows", &apikey("bob", vec!["team-a"]), Role::Editor);
+    let ids = vec!["bob", "bob@example.com", "team-a"];
+    assert_eq!(query, doc!{"$or": [
+      {"acl": null},
+      {"acl.owners": {"$in": ids.clone()}},
+      {"acl.editors": {"$in": ids}},
+    ]});
+  }
+}
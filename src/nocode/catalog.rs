@@ -0,0 +1,179 @@
+use d3ne::*;
+use serde_json::Value;
+
+use crate::nocode::flow::FlowContext;
+
+/// Socket types, named like the sockets of the editor
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum SocketType {
+  Number,
+  Float,
+  String,
+  Bool,
+  Json,
+  Template,
+  Action,
+  #[serde(rename = "ANY")]
+  Any,
+}
+
+impl SocketType {
+  /// Whether an output of this type can be connected to an input of `input`
+  pub fn connects_to(self, input: SocketType) -> bool {
+    self == input || (input == SocketType::Any && self != SocketType::Action && self != SocketType::Template)
+  }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Socket {
+  pub key: &'static str,
+  #[serde(rename = "type")]
+  pub socket: SocketType,
+  /// the node fails when a required input is neither connected nor set on the node data
+  pub required: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Control {
+  pub key: &'static str,
+  pub default: Value,
+}
+
+/// Outputs named by the entries of a list in the node data, like the cases of a Switch.
+/// Each entry is an object with a `name`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DynamicOutputs {
+  pub control: &'static str,
+  #[serde(rename = "type")]
+  pub socket: SocketType,
+}
+
+/// What a node looks like to the editor: its sockets and the fields it keeps in its data
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeSpec {
+  pub name: String,
+  pub inputs: Vec<Socket>,
+  pub outputs: Vec<Socket>,
+  pub controls: Vec<Control>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub dynamic_outputs: Option<DynamicOutputs>,
+}
+
+impl NodeSpec {
+  pub fn new(name: &str) -> Self {
+    NodeSpec { name: name.to_string(), inputs: vec![], outputs: vec![], controls: vec![], dynamic_outputs: None }
+  }
+
+  /// The action input that orders nodes with side effects
+  pub fn action(self) -> Self {
+    self.optional("action", SocketType::Action)
+  }
+
+  pub fn input(mut self, key: &'static str, socket: SocketType) -> Self {
+    self.inputs.push(Socket { key, socket, required: true });
+    self
+  }
+
+  pub fn optional(mut self, key: &'static str, socket: SocketType) -> Self {
+    self.inputs.push(Socket { key, socket, required: false });
+    self
+  }
+
+  pub fn output(mut self, key: &'static str, socket: SocketType) -> Self {
+    self.outputs.push(Socket { key, socket, required: false });
+    self
+  }
+
+  pub fn control(mut self, key: &'static str, default: Value) -> Self {
+    self.controls.push(Control { key, default });
+    self
+  }
+
+  /// Adds an output of type `socket` for every entry of the `control` list
+  pub fn dynamic_outputs(self, control: &'static str, socket: SocketType) -> Self {
+    let mut spec = self.control(control, json!([]));
+    spec.dynamic_outputs = Some(DynamicOutputs { control, socket });
+    spec
+  }
+
+  pub fn input_socket(&self, key: &str) -> Option<&Socket> {
+    self.inputs.iter().find(|s| s.key == key)
+  }
+
+  pub fn output_socket(&self, key: &str) -> Option<&Socket> {
+    self.outputs.iter().find(|s| s.key == key)
+  }
+
+  /// The type of output `key` on a node with `data`, including its dynamic outputs
+  pub fn output_type(&self, key: &str, data: &Value) -> Option<SocketType> {
+    self.output_socket(key).map(|s| s.socket).or_else(|| self.dynamic_outputs.as_ref()
+      .filter(|d| data[d.control].as_array().into_iter().flatten().any(|entry| entry["name"] == key))
+      .map(|d| d.socket))
+  }
+}
+
+/// Implemented next to every `Worker` so the catalog is built from the workers themselves
+pub trait Describe {
+  fn describe(&self) -> NodeSpec;
+}
+
+/// The workers an engine is built with, along with their descriptions
+pub struct Registry {
+  ctx: FlowContext,
+  workers: WorkersBuilder,
+  specs: Vec<NodeSpec>,
+}
+
+impl Registry {
+  pub fn new(ctx: &FlowContext) -> Self {
+    Registry { ctx: ctx.clone(), workers: WorkersBuilder::new(), specs: vec![] }
+  }
+
+  pub fn add<W: Worker + Describe + 'static>(&mut self, worker: W) -> &mut Self {
+    self.specs.push(worker.describe());
+    self.workers.add(self.ctx.guard(worker));
+    self
+  }
+
+  pub fn specs(&self) -> &[NodeSpec] {
+    &self.specs
+  }
+
+  pub fn engine(self, id: &str) -> Engine {
+    Engine::new(id, self.workers.build())
+  }
+}
+
+#[cfg(test)]
+mod catalog_test {
+  use d3ne::Worker;
+  use crate::nocode::catalog::{Describe, SocketType};
+  use crate::nocode::flow::FlowContext;
+  use crate::nocode::nodes;
+
+  #[test]
+  fn describes_workers() {
+    let spec = nodes::ArrayFlatten.describe();
+    assert_eq!(spec.name, nodes::ArrayFlatten.name());
+    assert_eq!(spec.name, "Array Flatten");
+    assert!(spec.input_socket("payload").map(|s| s.required).unwrap_or(false));
+    assert_eq!(spec.output_socket("json").map(|s| s.socket), Some(SocketType::Json));
+  }
+
+  #[test]
+  fn dynamic_outputs() {
+    let spec = nodes::Switch(FlowContext::new()).describe();
+    let data = json!({"cases": [{"name": "order"}]});
+    assert_eq!(spec.output_type("order", &data), Some(SocketType::Action));
+    assert_eq!(spec.output_type("default", &data), Some(SocketType::Action));
+    assert_eq!(spec.output_type("refund", &data), None);
+  }
+
+  #[test]
+  fn socket_compatibility() {
+    assert!(SocketType::Number.connects_to(SocketType::Any));
+    assert!(!SocketType::Number.connects_to(SocketType::Json));
+    assert!(!SocketType::Action.connects_to(SocketType::Any));
+    assert!(SocketType::Action.connects_to(SocketType::Action));
+  }
+}
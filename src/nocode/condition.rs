@@ -0,0 +1,153 @@
+use std::cmp::Ordering;
+use serde_json::Value;
+use bson::{Bson, Document};
+use anyhow::Result;
+use regex::Regex;
+
+use querylib::{mongo, query};
+
+/// Evaluates a Condition node. `condition` is either one of the comparison
+/// operators (`==`, `!=`, `<`, `<=`, `>`, `>=`, `contains`, `regex`) applied as
+/// `left <op> right`, or a querylib expression over the fields `left` and `right`.
+pub fn evaluate(condition: &str, left: &Value, right: &Value) -> Result<bool> {
+  let cond = condition.trim();
+  Ok(match cond {
+    "" | "==" => compare(left, right) == Some(Ordering::Equal),
+    "!=" => compare(left, right) != Some(Ordering::Equal),
+    "<" => compare(left, right) == Some(Ordering::Less),
+    "<=" => matches!(compare(left, right), Some(Ordering::Less) | Some(Ordering::Equal)),
+    ">" => compare(left, right) == Some(Ordering::Greater),
+    ">=" => matches!(compare(left, right), Some(Ordering::Greater) | Some(Ordering::Equal)),
+    "contains" => contains(left, right),
+    "regex" => match (left, right) {
+      (Value::String(s), Value::String(re)) => Regex::new(re)?.is_match(s),
+      _ => bail!("regex condition needs a string on both sides"),
+    },
+    _ => {
+      let filter = mongo::to_bson(query::parse::from_str(cond));
+      matches_filter(&filter, &json!({ "left": left, "right": right }))?
+    }
+  })
+}
+
+fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+  match (left, right) {
+    (Value::Number(l), Value::Number(r)) => l.as_f64().partial_cmp(&r.as_f64()),
+    (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+    (Value::Bool(l), Value::Bool(r)) => Some(l.cmp(r)),
+    (Value::Null, Value::Null) => Some(Ordering::Equal),
+    (l, r) => if l == r { Some(Ordering::Equal) } else { None },
+  }
+}
+
+fn contains(left: &Value, right: &Value) -> bool {
+  match (left, right) {
+    (Value::String(l), Value::String(r)) => l.contains(r.as_str()),
+    (Value::Array(l), r) => l.iter().any(|v| compare(v, r) == Some(Ordering::Equal)),
+    (Value::Object(l), Value::String(r)) => l.contains_key(r),
+    _ => false,
+  }
+}
+
+/// Matches a mongo style filter document, as produced by `mongo::to_bson`, against a json value
+pub fn matches_filter(filter: &Document, value: &Value) -> Result<bool> {
+  for (key, cond) in filter {
+    let ok = match key.as_str() {
+      "$and" => each_filter(cond, value)?.into_iter().all(|b| b),
+      "$or" => each_filter(cond, value)?.into_iter().any(|b| b),
+      "$nor" => !each_filter(cond, value)?.into_iter().any(|b| b),
+      field => matches_field(lookup(value, field), cond)?,
+    };
+    if !ok {
+      return Ok(false);
+    }
+  }
+  Ok(true)
+}
+
+fn each_filter(b: &Bson, value: &Value) -> Result<Vec<bool>> {
+  match b {
+    Bson::Array(arr) => arr.iter()
+      .map(|f| f.as_document().ok_or(anyhow!("expected a document in logical operator")))
+      .map(|f| f.and_then(|f| matches_filter(f, value)))
+      .collect(),
+    _ => bail!("expected an array in logical operator"),
+  }
+}
+
+fn lookup<'a>(value: &'a Value, path: &str) -> &'a Value {
+  path.split('.').fold(value, |v, p| match v {
+    Value::Array(arr) => p.parse::<usize>().ok().and_then(|i| arr.get(i)).unwrap_or(&Value::Null),
+    _ => v.get(p).unwrap_or(&Value::Null),
+  })
+}
+
+fn matches_field(value: &Value, cond: &Bson) -> Result<bool> {
+  match cond {
+    Bson::Document(ops) if ops.keys().all(|k| k.starts_with('$')) => {
+      for (op, arg) in ops {
+        let expected = arg.clone().into_relaxed_extjson();
+        let ok = match op.as_str() {
+          "$eq" => compare(value, &expected) == Some(Ordering::Equal),
+          "$ne" => compare(value, &expected) != Some(Ordering::Equal),
+          "$gt" => compare(value, &expected) == Some(Ordering::Greater),
+          "$gte" => matches!(compare(value, &expected), Some(Ordering::Greater) | Some(Ordering::Equal)),
+          "$lt" => compare(value, &expected) == Some(Ordering::Less),
+          "$lte" => matches!(compare(value, &expected), Some(Ordering::Less) | Some(Ordering::Equal)),
+          "$in" => expected.as_array().map(|a| a.iter().any(|e| in_value(value, e))).unwrap_or(false),
+          "$nin" => !expected.as_array().map(|a| a.iter().any(|e| in_value(value, e))).unwrap_or(false),
+          "$exists" => (*value != Value::Null) == expected.as_bool().unwrap_or(true),
+          "$regex" => regex_matches(value, arg)?,
+          "$options" => true,
+          "$not" => !matches_field(value, arg)?,
+          other => bail!("unsupported operator in condition: {}", other),
+        };
+        if !ok {
+          return Ok(false);
+        }
+      }
+      Ok(true)
+    },
+    Bson::RegularExpression(_) => regex_matches(value, cond),
+    other => Ok(in_value(value, &other.clone().into_relaxed_extjson())),
+  }
+}
+
+fn in_value(value: &Value, expected: &Value) -> bool {
+  match value {
+    Value::Array(arr) => arr.iter().any(|v| compare(v, expected) == Some(Ordering::Equal)),
+    _ => compare(value, expected) == Some(Ordering::Equal),
+  }
+}
+
+fn regex_matches(value: &Value, re: &Bson) -> Result<bool> {
+  let pattern = match re {
+    Bson::RegularExpression(r) if r.options.is_empty() => r.pattern.clone(),
+    Bson::RegularExpression(r) => format!("(?{}){}", r.options, r.pattern),
+    Bson::String(s) => s.clone(),
+    _ => bail!("regex operator needs a string pattern"),
+  };
+  Ok(value.as_str().map(|s| Regex::new(&pattern).map(|r| r.is_match(s))).transpose()?.unwrap_or(false))
+}
+
+#[cfg(test)]
+mod condition_test {
+  use crate::nocode::condition::evaluate;
+
+  #[test]
+  fn compares() {
+    assert!(evaluate("==", &json!(5), &json!(5.0)).unwrap());
+    assert!(evaluate("!=", &json!("a"), &json!("b")).unwrap());
+    assert!(evaluate("<", &json!(1), &json!(2)).unwrap());
+    assert!(!evaluate(">", &json!(1), &json!(2)).unwrap());
+    assert!(evaluate("contains", &json!("hello, world"), &json!("world")).unwrap());
+    assert!(evaluate("contains", &json!([1, 2, 3]), &json!(2)).unwrap());
+    assert!(evaluate("regex", &json!("abc123"), &json!(r"^[a-z]+\d+$")).unwrap());
+  }
+
+  #[test]
+  fn queries() {
+    assert!(evaluate("left.age > 18 && right == 'yes'", &json!({"age": 21}), &json!("yes")).unwrap());
+    assert!(!evaluate("left.age > 18 && right == 'yes'", &json!({"age": 12}), &json!("yes")).unwrap());
+  }
+}
@@ -0,0 +1,86 @@
+use std::collections::{BTreeMap, BTreeSet};
+use serde_json::Value;
+
+type Connection = (String, String, String, String);
+
+/// Compares two stored flow graphs and reports the nodes and connections that
+/// were added, removed or changed. Node positions are ignored.
+pub fn diff_flows(from: &Value, to: &Value) -> Value {
+  let from_nodes = nodes_of(from);
+  let to_nodes = nodes_of(to);
+
+  let node_json = |n: &&Value| json!({ "id": n["id"], "name": n["name"] });
+  let added: Vec<Value> = to_nodes.iter().filter(|(id, _)| !from_nodes.contains_key(*id)).map(|(_, n)| node_json(n)).collect();
+  let removed: Vec<Value> = from_nodes.iter().filter(|(id, _)| !to_nodes.contains_key(*id)).map(|(_, n)| node_json(n)).collect();
+  let changed: Vec<Value> = from_nodes.iter()
+    .filter_map(|(id, old)| to_nodes.get(id).map(|new| (id, old, new)))
+    .filter_map(|(id, old, new)| {
+      let fields: Vec<&str> = ["name", "data"].iter()
+        .filter(|f| old[**f] != new[**f])
+        .cloned()
+        .collect();
+      if fields.is_empty() {
+        None
+      } else {
+        Some(json!({ "id": id, "name": new["name"], "fields": fields, "from": { "name": old["name"], "data": old["data"] }, "to": { "name": new["name"], "data": new["data"] } }))
+      }
+    })
+    .collect();
+
+  let from_conns = connections_of(&from_nodes);
+  let to_conns = connections_of(&to_nodes);
+  let conn_json = |c: &Connection| json!({ "node": c.0, "output": c.1, "to": c.2, "input": c.3 });
+
+  json!({
+    "nodes": {
+      "added": added,
+      "removed": removed,
+      "changed": changed,
+    },
+    "connections": {
+      "added": to_conns.difference(&from_conns).map(conn_json).collect::<Vec<_>>(),
+      "removed": from_conns.difference(&to_conns).map(conn_json).collect::<Vec<_>>(),
+    }
+  })
+}
+
+fn nodes_of(flow: &Value) -> BTreeMap<String, &Value> {
+  flow["nodes"].as_object()
+    .map(|o| o.iter().map(|(k, v)| (k.clone(), v)).collect())
+    .unwrap_or_default()
+}
+
+fn connections_of(nodes: &BTreeMap<String, &Value>) -> BTreeSet<Connection> {
+  let mut set = BTreeSet::new();
+  for (id, node) in nodes {
+    for (output, out) in node["outputs"].as_object().into_iter().flatten() {
+      for conn in out["connections"].as_array().into_iter().flatten() {
+        set.insert((id.clone(), output.clone(), conn["node"].to_string(), conn["input"].as_str().unwrap_or_default().to_string()));
+      }
+    }
+  }
+  set
+}
+
+#[cfg(test)]
+mod diff_test {
+  use crate::nocode::diff::diff_flows;
+
+  #[test]
+  fn diffs() {
+    let from = json!({"nodes": {
+      "1": { "id": 1, "name": "Number", "data": { "num": 1 }, "position": [0, 0], "outputs": { "num": { "connections": [{ "node": 2, "input": "num" }] } } },
+      "2": { "id": 2, "name": "Add", "data": {}, "position": [0, 0], "outputs": {} }
+    }});
+    let to = json!({"nodes": {
+      "1": { "id": 1, "name": "Number", "data": { "num": 5 }, "position": [10, 10], "outputs": { "num": { "connections": [{ "node": 3, "input": "num" }] } } },
+      "3": { "id": 3, "name": "Multiply", "data": {}, "position": [0, 0], "outputs": {} }
+    }});
+    let diff = diff_flows(&from, &to);
+    assert_eq!(diff["nodes"]["added"], json!([{ "id": 3, "name": "Multiply" }]));
+    assert_eq!(diff["nodes"]["removed"], json!([{ "id": 2, "name": "Add" }]));
+    assert_eq!(diff["nodes"]["changed"][0]["fields"], json!(["data"]));
+    assert_eq!(diff["connections"]["added"], json!([{ "node": "1", "output": "num", "to": "3", "input": "num" }]));
+    assert_eq!(diff["connections"]["removed"], json!([{ "node": "1", "output": "num", "to": "2", "input": "num" }]));
+  }
+}
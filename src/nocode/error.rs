@@ -0,0 +1,178 @@
+use d3ne::Node;
+use regex::Regex;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket_contrib::json::JsonValue;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+  BadRequest,
+  Unauthorized,
+  Forbidden,
+  NotFound,
+  Conflict,
+  InvalidGraph,
+  InvalidInput,
+  Script,
+  Node,
+  Database,
+  Unavailable,
+  Internal,
+}
+
+impl ErrorKind {
+  pub fn status(self) -> Status {
+    match self {
+      ErrorKind::BadRequest => Status::BadRequest,
+      ErrorKind::Unauthorized => Status::Unauthorized,
+      ErrorKind::Forbidden => Status::Forbidden,
+      ErrorKind::NotFound => Status::NotFound,
+      ErrorKind::Conflict => Status::Conflict,
+      ErrorKind::InvalidGraph | ErrorKind::InvalidInput | ErrorKind::Script => Status::UnprocessableEntity,
+      ErrorKind::Unavailable => Status::ServiceUnavailable,
+      ErrorKind::Node | ErrorKind::Database | ErrorKind::Internal => Status::InternalServerError,
+    }
+  }
+}
+
+/// The error every endpoint responds with, under `error`. Errors raised while
+/// running a graph carry the node that failed and, for scripts, where in the source.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiError {
+  pub kind: ErrorKind,
+  pub message: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub node: Option<i64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub node_name: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub input: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub line: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub col: Option<u32>,
+}
+
+impl ApiError {
+  pub fn new<S: Into<String>>(kind: ErrorKind, message: S) -> Self {
+    ApiError { kind, message: message.into(), node: None, node_name: None, input: None, line: None, col: None }
+  }
+
+  pub fn input(mut self, input: &str) -> Self {
+    self.input = Some(input.to_string());
+    self
+  }
+
+  pub fn at(mut self, line: u32, col: u32) -> Self {
+    self.line = Some(line);
+    self.col = Some(col);
+    self
+  }
+
+  /// Ties the error to `node` unless a nested node already claimed it
+  pub fn on(mut self, node: &Node) -> Self {
+    if self.node.is_none() {
+      self.node = Some(node.id);
+      self.node_name = Some(node.name.clone());
+    }
+    self
+  }
+
+  pub fn status(&self) -> Status {
+    self.kind.status()
+  }
+
+  /// The response for this error with the fields of `extra` next to it
+  pub fn respond(self, extra: Value) -> status::Custom<JsonValue> {
+    let mut body = json!({ "error": &self });
+    if let (Some(body), Value::Object(extra)) = (body.as_object_mut(), extra) {
+      body.extend(extra);
+    }
+    status::Custom(self.status(), body.into())
+  }
+
+  /// Reads the line and column of a failed script from the stack of the sandbox
+  pub fn script(error: &str) -> Self {
+    let re = Regex::new(r"(?s)^(?P<error>.+?)\n.*?sandboxed\.js:(?P<line>\d+):(?P<col>\d+)").expect("valid regex");
+    match re.captures(error) {
+      Some(c) => ApiError::new(ErrorKind::Script, &c["error"]).at(
+        c["line"].parse().unwrap_or_default(),
+        c["col"].parse().unwrap_or_default(),
+      ),
+      None => ApiError::new(ErrorKind::Script, error),
+    }
+  }
+}
+
+impl std::fmt::Display for ApiError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match (&self.node_name, self.node) {
+      (Some(name), Some(id)) => write!(f, "{} ({}): {}", name, id, self.message),
+      _ => write!(f, "{}", self.message),
+    }
+  }
+}
+
+impl std::error::Error for ApiError {}
+
+impl From<ApiError> for status::Custom<JsonValue> {
+  fn from(e: ApiError) -> Self {
+    e.respond(Value::Null)
+  }
+}
+
+impl From<&anyhow::Error> for ApiError {
+  fn from(e: &anyhow::Error) -> Self {
+    if let Some(api_error) = e.chain().find_map(|c| c.downcast_ref::<ApiError>()) {
+      api_error.clone()
+    } else if e.chain().any(|c| c.is::<mongodb::error::Error>()) {
+      ApiError::new(ErrorKind::Database, e.to_string())
+    } else {
+      ApiError::new(ErrorKind::Node, e.to_string())
+    }
+  }
+}
+
+impl From<anyhow::Error> for ApiError {
+  fn from(e: anyhow::Error) -> Self {
+    ApiError::from(&e)
+  }
+}
+
+/// For `map_err` on reading a node input, so the error names the input
+pub fn invalid_input(input: &'static str) -> impl Fn(anyhow::Error) -> anyhow::Error {
+  move |e| ApiError::new(ErrorKind::InvalidInput, e.to_string()).input(input).into()
+}
+
+#[cfg(test)]
+mod error_test {
+  use rocket::http::Status;
+  use rocket::response::status;
+  use crate::nocode::error::{invalid_input, ApiError, ErrorKind};
+
+  #[test]
+  fn keeps_node_errors() {
+    let e: anyhow::Error = invalid_input("query")(anyhow!("field not found"));
+    let e = ApiError::from(e.context("processing failed"));
+    assert_eq!(e.kind, ErrorKind::InvalidInput);
+    assert_eq!(e.input.as_deref(), Some("query"));
+    assert_eq!(e.status(), Status::UnprocessableEntity);
+    assert_eq!(ApiError::from(anyhow!("boom")).kind, ErrorKind::Node);
+  }
+
+  #[test]
+  fn reads_script_position() {
+    let e = ApiError::script("ReferenceError: x is not defined\n    at main (sandboxed.js:1:48)\n    at <anonymous>:1:1");
+    assert_eq!(e.message, "ReferenceError: x is not defined");
+    assert_eq!((e.line, e.col), (Some(1), Some(48)));
+  }
+
+  #[test]
+  fn serializes_with_extra_fields() {
+    let status::Custom(status, body) = ApiError::new(ErrorKind::NotFound, "revision not found").respond(json!({"rev": 3}));
+    assert_eq!(status, Status::NotFound);
+    assert_eq!(body.0, json!({"error": {"kind": "not_found", "message": "revision not found"}, "rev": 3}));
+  }
+}
@@ -0,0 +1,233 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::thread;
+use anyhow::Result;
+
+use d3ne::*;
+
+use crate::nocode::error::{ApiError, ErrorKind};
+use crate::nocode::retry::{RetryBudget, RetryPolicy};
+
+#[derive(Default)]
+struct FlowState {
+  nodes: HashMap<i64, Node>,
+  closed: HashSet<i64>,
+  output: Option<OutputData>,
+  /// outputs of the nodes that ran, replayed in later passes
+  ran: HashMap<i64, OutputData>,
+  /// errors caught by Try nodes, by the id of the Try
+  caught: HashMap<i64, ApiError>,
+  pass: usize,
+  /// whether a Try caught an error during this pass
+  pending: bool,
+  trace: Vec<Step>,
+  budget: RetryBudget,
+}
+
+/// A node that ran, in the trace of a run
+#[derive(Debug, Clone, Serialize)]
+pub struct Step {
+  pub node: i64,
+  pub name: String,
+  pub attempts: u32,
+  /// the kind of error it failed with after its last attempt
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub error: Option<ErrorKind>,
+}
+
+/// State shared by all the workers of a single flow run, used to skip the
+/// nodes that sit behind a branch that was not taken and to hand the errors
+/// of a guarded branch to its Try node.
+#[derive(Clone, Default)]
+pub struct FlowContext(Rc<RefCell<FlowState>>);
+
+impl FlowContext {
+  pub fn new() -> Self {
+    FlowContext::default()
+  }
+
+  /// For a run whose retries draw on the wait `budget` of the run that started it
+  pub fn with_budget(budget: RetryBudget) -> Self {
+    FlowContext(Rc::new(RefCell::new(FlowState { budget, ..FlowState::default() })))
+  }
+
+  pub fn budget(&self) -> RetryBudget {
+    self.0.borrow().budget.clone()
+  }
+
+  pub fn load(&self, nodes: &HashMap<i64, Node>) {
+    let mut state = self.0.borrow_mut();
+    *state = FlowState { nodes: nodes.clone(), budget: state.budget.clone(), ..FlowState::default() };
+  }
+
+  pub fn guard<W: Worker>(&self, worker: W) -> Guarded<W> {
+    Guarded(worker, self.clone())
+  }
+
+  pub fn is_closed(&self, id: i64) -> bool {
+    self.0.borrow().closed.contains(&id)
+  }
+
+  /// Closes every node reachable from the `untaken` outputs of `id` that is
+  /// not also reachable from one of its `taken` outputs.
+  pub fn close_branches(&self, id: i64, taken: &[&str], untaken: &[&str]) {
+    let mut state = self.0.borrow_mut();
+    let keep = reachable(&state.nodes, id, taken);
+    let drop = reachable(&state.nodes, id, untaken);
+    state.closed.extend(drop.difference(&keep));
+  }
+
+  /// The result of the last Output node that was executed
+  pub fn output(&self) -> Option<OutputData> {
+    self.0.borrow().output.as_ref().map(share)
+  }
+
+  fn set_output(&self, output: OutputData) {
+    self.0.borrow_mut().output = Some(output);
+  }
+
+  /// Hands `error` of node `id` to the innermost Try whose `try` branch holds the
+  /// node, closing the rest of that branch. False when no Try guards the node.
+  fn catch(&self, id: i64, error: &ApiError) -> bool {
+    let mut state = self.0.borrow_mut();
+    let guard = state.nodes.values()
+      .filter(|n| n.name == "Try" && !state.caught.contains_key(&n.id))
+      .map(|n| (n.id, reachable(&state.nodes, n.id, &["try"])))
+      .filter(|(_, branch)| branch.contains(&id))
+      .min_by_key(|(_, branch)| branch.len());
+    match guard {
+      Some((try_id, branch)) => {
+        state.caught.insert(try_id, error.clone());
+        state.closed.extend(branch);
+        state.pending = true;
+        true
+      },
+      None => false,
+    }
+  }
+
+  /// The error the Try node `id` caught in an earlier pass
+  pub fn caught(&self, id: i64) -> Option<ApiError> {
+    self.0.borrow().caught.get(&id).cloned()
+  }
+
+  /// Prepares another pass over the graph when a Try caught an error: its `error`
+  /// branch is opened and every node that already ran replays its output.
+  pub fn next_pass(&self) -> bool {
+    let mut state = self.0.borrow_mut();
+    if !state.pending {
+      return false;
+    }
+    state.pending = false;
+    state.pass += 1;
+    let reopened: HashSet<i64> = state.caught.keys()
+      .flat_map(|id| reachable(&state.nodes, *id, &["error"]))
+      .collect();
+    state.closed.retain(|id| !reopened.contains(id));
+    true
+  }
+
+  fn replay(&self, id: i64) -> Option<OutputData> {
+    let state = self.0.borrow();
+    match state.pass {
+      0 => None,
+      _ if state.caught.contains_key(&id) => None,
+      _ => state.ran.get(&id).map(share),
+    }
+  }
+
+  fn ran(&self, id: i64, output: &OutputData) {
+    self.0.borrow_mut().ran.insert(id, share(output));
+  }
+
+  fn step(&self, node: &Node, attempts: u32, error: Option<ErrorKind>) {
+    self.0.borrow_mut().trace.push(Step { node: node.id, name: node.name.clone(), attempts, error });
+  }
+
+  /// The nodes that ran so far, in the order they ran
+  pub fn trace(&self) -> Vec<Step> {
+    self.0.borrow().trace.clone()
+  }
+}
+
+/// Another handle on `output`, which d3ne doesn't make cloneable
+fn share(output: &OutputData) -> OutputData {
+  OutputData(Rc::clone(&output.0))
+}
+
+fn share_inputs(inputs: &InputData) -> InputData {
+  InputData(inputs.iter().map(|(key, output)| (key.clone(), share(output))).collect())
+}
+
+fn reachable(nodes: &HashMap<i64, Node>, id: i64, outputs: &[&str]) -> HashSet<i64> {
+  let mut seen = HashSet::new();
+  let mut stack: Vec<i64> = nodes.get(&id)
+    .and_then(|n| n.outputs.clone())
+    .map(|o| outputs.iter()
+      .filter_map(|name| o.get(*name))
+      .flat_map(|out| out.connections.iter().map(|c| c.node).collect::<Vec<_>>())
+      .collect())
+    .unwrap_or_default();
+  while let Some(next) = stack.pop() {
+    if next == id || !seen.insert(next) {
+      continue;
+    }
+    if let Some(outs) = nodes.get(&next).and_then(|n| n.outputs.clone()) {
+      stack.extend(outs.values().flat_map(|out| out.connections.iter().map(|c| c.node)));
+    }
+  }
+  seen
+}
+
+/// Wraps a worker so it is skipped when its node is on a closed branch,
+/// runs it again as its retry policy allows, and so its errors name the node
+/// that failed and go to a Try guarding it
+pub struct Guarded<W: Worker>(pub W, pub FlowContext);
+impl<W: Worker> Worker for Guarded<W> {
+  fn name(&self) -> &str {
+    self.0.name()
+  }
+  fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
+    if self.1.is_closed(node.id) {
+      return Ok(OutputDataBuilder::new().build());
+    }
+    if let Some(output) = self.1.replay(node.id) {
+      return Ok(output);
+    }
+    let (result, attempts) = match RetryPolicy::of(node) {
+      Ok(policy) => attempt(&policy, &self.1.budget(), || self.0.work(node, share_inputs(&input_data)).map_err(|e| ApiError::from(e).on(node))),
+      Err(e) => (Err(e.on(node)), 0),
+    };
+    self.1.step(node, attempts, result.as_ref().err().map(|e| e.kind));
+    let output = match result {
+      Ok(output) => output,
+      Err(error) => {
+        if self.1.catch(node.id, &error) {
+          return Ok(OutputDataBuilder::new().build());
+        }
+        return Err(error.into());
+      },
+    };
+    self.1.ran(node.id, &output);
+    if node.name == "Output" {
+      self.1.set_output(share(&output));
+    }
+    Ok(output)
+  }
+}
+
+/// Runs `work` until it succeeds, `policy` gives up or the run has no time left
+/// to wait for another attempt, with the number of attempts made
+fn attempt<T>(policy: &RetryPolicy, budget: &RetryBudget, work: impl Fn() -> Result<T, ApiError>) -> (Result<T, ApiError>, u32) {
+  let mut attempts = 1;
+  loop {
+    match work() {
+      Err(e) if policy.retries(&e, attempts) && budget.take(policy.backoff(attempts)) => {
+        thread::sleep(policy.backoff(attempts));
+        attempts += 1;
+      },
+      result => return (result, attempts),
+    }
+  }
+}
@@ -0,0 +1,16 @@
+/// The kinds of graph documents the engine runs. Flows and rules share the
+/// handlers and nodes, each is kept in its own database under its own scope.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Kind {
+  /// database, revision collection and api key scope
+  pub name: &'static str,
+  /// collection with one meta document per name
+  pub meta: &'static str,
+  /// field holding the graph on a stored revision and in request bodies
+  pub graph: &'static str,
+  pub engine: &'static str,
+}
+
+pub const FLOWS: Kind = Kind { name: "flows", meta: "flowsmeta", graph: "flow", engine: "flows@1.0.0" };
+
+pub const RULES: Kind = Kind { name: "rules", meta: "rulesmeta", graph: "rule", engine: "rules@1.0.0" };
@@ -1,25 +1,42 @@
 pub mod nodes;
+pub mod flow;
+pub mod policy;
+mod condition;
+mod diff;
+pub mod acl;
+pub mod kind;
+pub mod catalog;
+pub mod validate;
+pub mod error;
+pub mod subflow;
+pub mod retry;
 
-use rocket::http::Cookies;
-use serde_json::Map;
 use bson::Document;
-use mongodb::sync::Collection;
+use mongodb::sync::{Collection, Database};
+use mongodb::error::{ErrorKind as MongoErrorKind, WriteFailure};
 use std::convert::TryFrom;
-use mongodb::options::{FindOptions, FindOneAndUpdateOptions};
+use mongodb::options::{FindOptions, FindOneOptions, FindOneAndUpdateOptions, ReturnDocument, UpdateModifications};
 use serde_json::Value;
 use std::rc::Rc;
-use std::convert::TryInto;
 use mongodb::sync::Client;
 use rocket::State;
 use rocket_contrib::json::{JsonValue, Json};
-use rocket::response::status;
+use rocket::response::{self, status, Responder};
 use rocket::http::Status;
+use rocket::request::Request;
 use d3ne::*;
 
 use querylib::{mongo, query, query::*};
 
-// use crate::apikey::{check_access, get_apikey_without_bearer, ApiKey};
-use crate::util::current_millis;
+use crate::apikey::{check_access, ApiKey, ApiKeyError};
+use crate::util::{bson_or_null, current_millis, unauthorized};
+use flow::{FlowContext, Step};
+use catalog::Registry;
+use policy::{MongoPolicy, Tenant};
+use acl::{Acl, Role};
+use error::{ApiError, ErrorKind};
+use subflow::SubFlows;
+pub use kind::{Kind, FLOWS, RULES};
 
 #[derive(Debug, Serialize, Clone)]
 pub enum NoCodeError {
@@ -27,16 +44,52 @@ pub enum NoCodeError {
   SystemTimeError,
   BsonError,
   JsonError,
+  InvalidPayload(String),
+  InvalidGraph(String),
+  NotFound(String),
+  Recursion(String),
+  MissingOutput,
+  InvalidStatus(i64),
+  Run(ApiError),
 }
 
 impl std::fmt::Display for NoCodeError {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(f, "{}", match self {
-      NoCodeError::MongoError(_) => "Database error",
-      NoCodeError::SystemTimeError => "SystemTimeError",
-      NoCodeError::BsonError => "BsonError",
-      NoCodeError::JsonError => "JsonError",
-    })
+    match self {
+      NoCodeError::MongoError(_) => write!(f, "Database error"),
+      NoCodeError::SystemTimeError => write!(f, "SystemTimeError"),
+      NoCodeError::BsonError => write!(f, "BsonError"),
+      NoCodeError::JsonError => write!(f, "JsonError"),
+      NoCodeError::InvalidPayload(e) => write!(f, "{}", e),
+      NoCodeError::InvalidGraph(e) => write!(f, "{}", e),
+      NoCodeError::NotFound(e) => write!(f, "{}", e),
+      NoCodeError::Recursion(e) => write!(f, "{}", e),
+      NoCodeError::MissingOutput => write!(f, "the run did not reach an Output node"),
+      NoCodeError::InvalidStatus(status) => write!(f, "{} is not a valid http status", status),
+      NoCodeError::Run(e) => write!(f, "{}", e),
+    }
+  }
+}
+
+impl From<NoCodeError> for ApiError {
+  fn from(e: NoCodeError) -> Self {
+    match e {
+      NoCodeError::MongoError(message) => ApiError::new(ErrorKind::Database, message),
+      NoCodeError::InvalidPayload(message) => ApiError::new(ErrorKind::BadRequest, message),
+      NoCodeError::InvalidGraph(message) => ApiError::new(ErrorKind::InvalidGraph, message),
+      NoCodeError::NotFound(message) => ApiError::new(ErrorKind::NotFound, message),
+      NoCodeError::Recursion(message) => ApiError::new(ErrorKind::InvalidGraph, message),
+      NoCodeError::MissingOutput => ApiError::new(ErrorKind::InvalidGraph, e.to_string()),
+      NoCodeError::InvalidStatus(_) => ApiError::new(ErrorKind::InvalidInput, e.to_string()).input("status"),
+      NoCodeError::Run(e) => e,
+      e => ApiError::new(ErrorKind::Internal, e.to_string()),
+    }
+  }
+}
+
+impl<'r> Responder<'r> for NoCodeError {
+  fn respond_to(self, request: &Request) -> response::Result<'r> {
+    ApiError::from(self).respond(Value::Null).respond_to(request)
   }
 }
 
@@ -70,10 +123,13 @@ impl From<mongodb::error::Error> for NoCodeError {
   }
 }
 
-fn setup_engine<'a>(id: &'a str, connection: State<Client>, payload: Value) -> Engine<'a> {
-  let mut workers = WorkersBuilder::new();
-  let conn: Rc<Client> = Rc::new(connection.clone());
-  workers.add(nodes::Input(payload))
+/// Every node the engine knows, the same list backs the `/nodes` catalog
+fn registry(subflows: &SubFlows, payload: Value, ctx: &FlowContext) -> Registry {
+  let mut registry = Registry::new(ctx);
+  let conn: Rc<Client> = Rc::new(subflows.conn.clone());
+  let policy: Rc<MongoPolicy> = Rc::new(subflows.policy.clone());
+  let tenant = subflows.tenant.clone();
+  registry.add(nodes::Input(payload))
     .add(nodes::Output)
     .add(nodes::Number)
     .add(nodes::Float)
@@ -89,45 +145,317 @@ fn setup_engine<'a>(id: &'a str, connection: State<Client>, payload: Value) -> E
     .add(nodes::HandlebarsWorker)
     .add(nodes::Combine)
     .add(nodes::ScriptWorker)
-    .add(nodes::MongodbGet(conn.clone()))
-    .add(nodes::MongodbInsert(conn.clone()))
-    .add(nodes::MongodbInsert(conn))
+    .add(nodes::Condition(ctx.clone()))
+    .add(nodes::Switch(ctx.clone()))
+    .add(nodes::Try(ctx.clone()))
+    .add(nodes::MongodbGet(conn.clone(), policy.clone(), tenant.clone()))
+    .add(nodes::MongodbInsert(conn.clone(), policy.clone(), tenant.clone()))
+    .add(nodes::MongodbReplace(conn.clone(), policy.clone(), tenant.clone()))
+    .add(nodes::MongodbUpdate(conn.clone(), policy.clone(), tenant.clone()))
+    .add(nodes::MongodbDelete(conn.clone(), policy.clone(), tenant.clone()))
+    .add(nodes::MongodbAggregate(conn, policy, tenant))
     .add(nodes::Head)
     .add(nodes::Nth)
     .add(nodes::ArrayMap)
-    .add(nodes::ArrayFlattern)
+    .add(nodes::ArrayFlatten)
     .add(nodes::ArraySum)
-    .add(nodes::ArrayCount);
+    .add(nodes::ArrayCount)
+    .add(nodes::ForEach(subflows.clone()))
+    .add(nodes::CallFlow(subflows.clone()));
+  registry
+}
+
+fn setup_engine(subflows: &SubFlows, payload: Value, ctx: &FlowContext) -> Engine<'static> {
+  registry(subflows, payload, ctx).engine(subflows.kind.engine)
+}
 
-  Engine::new(id, workers.build())
+/// Flows listed in `ANONYMOUS_FLOWS` (rules in `ANONYMOUS_RULES`, comma separated,
+/// `tenant/name` for those that belong to a tenant) can be run without an api key
+fn allows_anonymous(kind: Kind, tenant: &Option<String>, name: &str) -> bool {
+  let full_name = match tenant {
+    Some(t) => format!("{}/{}", t, name),
+    None => name.to_string(),
+  };
+  std::env::var(format!("ANONYMOUS_{}", kind.name.to_uppercase()))
+    .map(|names| names.split(',').any(|n| n.trim() == full_name))
+    .unwrap_or(false)
+}
+
+/// Names taken by the endpoints next to `/flows/<name>` and `/rules/<name>`
+const RESERVED_NAMES: [&str; 1] = ["validate"];
+
+fn reserved_name(kind: Kind, name: &str) -> Option<status::Custom<JsonValue>> {
+  if RESERVED_NAMES.contains(&name) {
+    Some(ApiError::new(ErrorKind::BadRequest, format!("{} is reserved and can't name a {}", name, kind.graph)).input("name").into())
+  } else {
+    None
+  }
+}
+
+/// The tenant a caller's flows live in, the first entity on the api key.
+/// Keys without entities share the untenanted flows.
+fn tenant_of(apikey: &ApiKey) -> Option<String> {
+  apikey.entities.first().cloned()
+}
+
+/// Restricts a stored document, meta document or query to `tenant`
+fn scoped(mut doc: Document, tenant: &Option<String>) -> Document {
+  doc.insert("tenant", bson_or_null(tenant.clone()));
+  doc
+}
+
+/// The response for a run: the status and payload of its Output node, or the error
+/// with the node that raised it, along with the `trace` of the nodes that ran when given
+fn run_response(result: Result<OutputData, NoCodeError>, rev: i64, trace: Option<Vec<Step>>) -> status::Custom<JsonValue> {
+  let mut extra = json!({ "rev": rev, "timestamp": current_millis().unwrap_or_default() });
+  if let Some(trace) = trace {
+    extra["trace"] = json!(trace);
+  }
+  match result.and_then(|output| run_output(&output)) {
+    Ok((status, payload)) => {
+      extra["data"] = payload;
+      status::Custom(status, extra.into())
+    },
+    Err(e) => ApiError::from(e).respond(extra),
+  }
+}
+
+fn run_output(output: &OutputData) -> Result<(Status, Value), NoCodeError> {
+  let status = *output.get("status").and_then(|s| s.get::<i64>()).ok_or(NoCodeError::MissingOutput)?;
+  let payload = output.get("payload").and_then(|p| p.get::<Value>()).ok_or(NoCodeError::MissingOutput)?;
+  let code = u16::try_from(status).ok()
+    .filter(|code| (100..600).contains(code))
+    .ok_or(NoCodeError::InvalidStatus(status))?;
+  Ok((Status::from_code(code).unwrap_or_else(|| Status::new(code, "")), payload.clone()))
+}
+
+fn not_found(kind: Kind, name: &str) -> status::Custom<JsonValue> {
+  ApiError::new(ErrorKind::NotFound, format!("{} {} does not exist", kind.graph, name)).into()
+}
+
+/// Runs `flow` in passes, with an engine from `engine` for each since a d3ne engine is spent by its run
+fn process_flow<'a>(engine: impl Fn() -> Engine<'a>, ctx: &FlowContext, flow: Value) -> Result<OutputData, NoCodeError> {
+  let nodes = engine().parse_value(flow).map_err(|e| NoCodeError::InvalidGraph(e.to_string()))?;
+  ctx.load(&nodes);
+  // runs start at the Input node, or the first node of graphs without one
+  let start_node = nodes.values().filter(|n| n.name == "Input").map(|n| n.id).min()
+    .or_else(|| nodes.keys().min().copied())
+    .ok_or_else(|| NoCodeError::InvalidGraph("graph has no nodes".to_string()))?;
+  engine().process(&nodes, start_node).map_err(|e| NoCodeError::Run(ApiError::from(e)))?;
+  // errors caught by a Try are handled in another pass that runs its error branch
+  while ctx.next_pass() {
+    engine().process(&nodes, start_node).map_err(|e| NoCodeError::Run(ApiError::from(e)))?;
+  }
+  ctx.output().ok_or(NoCodeError::MissingOutput)
 }
 
 #[post("/flows/<name>/setactive", format="application/json", data="<data>")]
-pub fn set_active(name: String, data: Json<JsonValue>, _cookies: Cookies, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
-  let db = conn.database("flows");
-  let metacoll = db.collection("flowsmeta");
+pub fn set_active(name: String, data: Json<JsonValue>, apikey: ApiKey, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  set_active_doc(FLOWS, name, data, apikey, conn)
+}
 
-  let options = FindOneAndUpdateOptions::builder()
-    .upsert(false)
-    .build();
-  let pquery = query::parse::from_str(&format!("name == '{}'", name.clone()));
-  let query = mongo::to_bson(pquery);
-  match data.0["rev"].as_i64() {
-    Some(new_active) => {
-      let _res = metacoll.find_one_and_update(query.clone(), doc!("$set": doc!("active_rev": new_active)), Some(options))?;
-      Ok(status::Custom(Status::Ok, json!({ "name": name, "new_active": new_active }).into()))
+pub fn set_active_doc(kind: Kind, name: String, data: Json<JsonValue>, apikey: ApiKey, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  if !check_access(&apikey, kind.name, "save") {
+    return Ok(unauthorized());
+  }
+  let tenant = tenant_of(&apikey);
+  let db = conn.database(kind.name);
+  let coll: Collection = db.collection(kind.name);
+  let metacoll: Collection = db.collection(kind.meta);
+  let pquery = query!("name" == name.clone(););
+  let query = scoped(mongo::to_bson(pquery.clone()), &tenant);
+
+  let meta = match metacoll.find_one(query.clone(), None)? {
+    Some(m) => m,
+    None => return Ok(not_found(kind, &name)),
+  };
+  if !acl::allowed(kind.name, Acl::from_document(&meta), &apikey, Role::Editor) {
+    return Ok(unauthorized());
+  }
+  // meta documents from before activations were recorded have no active_rev, revision 1 is active
+  let stored_active = meta.get_i64("active_rev").ok();
+  let previous = stored_active.unwrap_or(1i64);
+
+  let target = match (&data.0["rev"], &data.0["tag"]) {
+    (Value::Number(n), _) => n.as_i64(),
+    (Value::String(s), _) if s == "latest" => meta.get_i64("latest_rev").ok(),
+    (Value::String(s), _) if s == "previous" => meta.get_array("activations").ok()
+      .and_then(|a| a.last())
+      .and_then(|a| a.as_document())
+      .and_then(|a| a.get_i64("from").ok()),
+    (Value::Null, Value::String(tag)) => {
+      let options = FindOneOptions::builder()
+        .sort(Some(doc!{"rev": -1}))
+        .build();
+      let (tagquery, tag) = (pquery.clone(), tag.clone());
+      coll.find_one(scoped(mongo::to_bson(query!(..tagquery && "tag" == tag)), &tenant), Some(options))?
+        .and_then(|d| d.get_i64("rev").ok())
     },
-    None => Ok(status::Custom(Status::Forbidden, json!({}).into()))
+    _ => return Ok(ApiError::new(ErrorKind::BadRequest, "rev must be a number, \"latest\" or \"previous\", or a tag must be given").into()),
+  };
+  let new_active = match target {
+    Some(rev) => rev,
+    None => return Ok(ApiError::new(ErrorKind::NotFound, "revision not found").respond(json!({"rev": data.0["rev"], "tag": data.0["tag"]}))),
+  };
+  if coll.count_documents(scoped(mongo::to_bson(query!(..pquery && "rev" == new_active)), &tenant), None)? == 0 {
+    return Ok(ApiError::new(ErrorKind::NotFound, "revision not found").respond(json!({"rev": new_active})));
+  }
+
+  // only switch if nobody activated another revision in the meantime
+  let activation = doc!("rev": new_active, "from": previous, "author": apikey.sub.clone(), "timestamp": current_millis()?);
+  let unchanged = match stored_active {
+    Some(rev) => doc!("name": name.clone(), "active_rev": rev),
+    None => doc!("name": name.clone(), "active_rev": doc!("$exists": false)),
+  };
+  let res = metacoll.update_one(
+    scoped(unchanged, &tenant),
+    doc!("$set": doc!("active_rev": new_active), "$push": doc!("activations": activation)),
+    None
+  )?;
+  if res.matched_count == 0 {
+    return Ok(ApiError::new(ErrorKind::Conflict, "active revision changed while activating").into());
+  }
+  Ok(status::Custom(Status::Ok, json!({ "name": name, "new_active": new_active, "previous_active": previous }).into()))
+}
+
+#[delete("/flows/<name>")]
+pub fn delete_flow(name: String, apikey: ApiKey, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  delete_doc(FLOWS, name, apikey, conn)
+}
+
+pub fn delete_doc(kind: Kind, name: String, apikey: ApiKey, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  if !check_access(&apikey, kind.name, "save") {
+    return Ok(unauthorized());
+  }
+  let tenant = tenant_of(&apikey);
+  let db = conn.database(kind.name);
+  let coll: Collection = db.collection(kind.name);
+  let metacoll: Collection = db.collection(kind.meta);
+  let query = scoped(mongo::to_bson(query!("name" == name.clone();)), &tenant);
+
+  match metacoll.find_one(query.clone(), None)? {
+    Some(meta) if !acl::allowed(kind.name, Acl::from_document(&meta), &apikey, Role::Owner) => return Ok(unauthorized()),
+    Some(_) => {},
+    None => return Ok(not_found(kind, &name)),
+  }
+  let res = coll.delete_many(query.clone(), None)?;
+  metacoll.delete_one(query, None)?;
+  Ok(status::Custom(Status::Ok, json!({ "name": name, "deleted_revs": res.deleted_count }).into()))
+}
+
+#[post("/flows/<name>/rename", format="application/json", data="<data>")]
+pub fn rename_flow(name: String, data: Json<JsonValue>, apikey: ApiKey, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  rename_doc(FLOWS, name, data, apikey, conn)
+}
+
+pub fn rename_doc(kind: Kind, name: String, data: Json<JsonValue>, apikey: ApiKey, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  if !check_access(&apikey, kind.name, "save") {
+    return Ok(unauthorized());
+  }
+  let tenant = tenant_of(&apikey);
+  let new_name = match data.0["name"].as_str() {
+    Some(n) if !n.is_empty() => n.to_string(),
+    _ => return Ok(ApiError::new(ErrorKind::BadRequest, "name is required").into()),
+  };
+  if let Some(response) = reserved_name(kind, &new_name) {
+    return Ok(response);
   }
+  let db = conn.database(kind.name);
+  let coll: Collection = db.collection(kind.name);
+  let metacoll: Collection = db.collection(kind.meta);
+  let query = scoped(mongo::to_bson(query!("name" == name.clone();)), &tenant);
+  let new_query = scoped(mongo::to_bson(query!("name" == new_name.clone();)), &tenant);
+
+  match metacoll.find_one(query.clone(), None)? {
+    Some(meta) if !acl::allowed(kind.name, Acl::from_document(&meta), &apikey, Role::Owner) => return Ok(unauthorized()),
+    Some(_) => {},
+    None => return Ok(not_found(kind, &name)),
+  }
+  if metacoll.count_documents(new_query, None)? > 0 {
+    return Ok(ApiError::new(ErrorKind::Conflict, format!("{} {} already exists", kind.graph, new_name)).into());
+  }
+  // revisions are moved along with the flow so its history is kept
+  let res = coll.update_many(query.clone(), doc!("$set": doc!("name": new_name.clone())), None)?;
+  metacoll.update_one(query, doc!("$set": doc!("name": new_name.clone())), None)?;
+  Ok(status::Custom(Status::Ok, json!({ "name": new_name, "old_name": name, "revs": res.modified_count }).into()))
 }
 
-#[post("/flows/<name>?<rev>",format="application/json", data="<data>")]
-pub fn run_flow(name: String, rev: Option<i64>, data: Json<JsonValue>, conn: State<Client>/*, apikey: ApiKey */) -> anyhow::Result<status::Custom<JsonValue>> {
-  let db = conn.database("flows");
-  let coll = db.collection("flows");
-  let metacoll = db.collection("flowsmeta");
-  let pquery = query::parse::from_str(&format!("name == '{}'", name));
-  let query = mongo::to_bson(pquery.clone());
+#[post("/flows/<name>/clone", format="application/json", data="<data>")]
+pub fn clone_flow(name: String, data: Json<JsonValue>, apikey: ApiKey, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  clone_doc(FLOWS, name, data, apikey, conn)
+}
+
+pub fn clone_doc(kind: Kind, name: String, data: Json<JsonValue>, apikey: ApiKey, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  if !check_access(&apikey, kind.name, "save") {
+    return Ok(unauthorized());
+  }
+  let tenant = tenant_of(&apikey);
+  let new_name = match data.0["name"].as_str() {
+    Some(n) if !n.is_empty() => n.to_string(),
+    _ => return Ok(ApiError::new(ErrorKind::BadRequest, "name is required").into()),
+  };
+  if let Some(response) = reserved_name(kind, &new_name) {
+    return Ok(response);
+  }
+  let db = conn.database(kind.name);
+  let coll: Collection = db.collection(kind.name);
+  let metacoll: Collection = db.collection(kind.meta);
+  let pquery = query!("name" == name.clone(););
+  let query = scoped(mongo::to_bson(pquery.clone()), &tenant);
+  let new_query = scoped(mongo::to_bson(query!("name" == new_name.clone();)), &tenant);
+
+  let meta = match metacoll.find_one(query, None)? {
+    Some(m) => m,
+    None => return Ok(not_found(kind, &name)),
+  };
+  if !acl::allowed(kind.name, Acl::from_document(&meta), &apikey, Role::Editor) {
+    return Ok(unauthorized());
+  }
+  if metacoll.count_documents(new_query.clone(), None)? > 0 {
+    return Ok(ApiError::new(ErrorKind::Conflict, format!("{} {} already exists", kind.graph, new_name)).into());
+  }
+  // the clone starts its own history from the requested revision, latest by default
+  let rev = data.0["rev"].as_i64().unwrap_or(meta.get_i64("latest_rev").unwrap_or(1i64));
+  let mut entry = match coll.find_one(scoped(mongo::to_bson(query!(..pquery && "rev" == rev)), &tenant), None)? {
+    Some(d) => d,
+    None => return Ok(ApiError::new(ErrorKind::NotFound, format!("revision {} not found", rev)).into()),
+  };
+  entry.remove("_id");
+  entry.insert("name", new_name.clone());
+  entry.insert("rev", 1i64);
+  entry.insert("timestamp", current_millis()?);
+  entry.insert("cloned_from", doc!("name": name.clone(), "rev": rev));
+  coll.insert_one(scoped(entry, &tenant), None)?;
+  metacoll.insert_one(scoped(doc!("name": new_name.clone(), "latest_rev": 1i64, "active_rev": 1i64, "rev_counter": 1i64, "acl": Acl::owned_by(&apikey).to_document()), &tenant), None)?;
+  Ok(status::Custom(Status::Ok, json!({ "name": new_name, "rev": 1, "cloned_from": { "name": name, "rev": rev } }).into()))
+}
+
+#[post("/flows/<name>?<rev>&<tenant>",format="application/json", data="<data>")]
+pub fn run_flow(name: String, rev: Option<i64>, tenant: Option<String>, data: Json<JsonValue>, conn: State<Client>, policy: State<MongoPolicy>, apikey: Result<ApiKey, ApiKeyError>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  run_doc(FLOWS, name, rev, tenant, data, conn, policy, apikey)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn run_doc(kind: Kind, name: String, rev: Option<i64>, tenant: Option<String>, data: Json<JsonValue>, conn: State<Client>, policy: State<MongoPolicy>, apikey: Result<ApiKey, ApiKeyError>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  // only callers without a key run anonymously, a bad, expired or revoked key is refused
+  let apikey = match apikey {
+    Ok(apikey) => Some(apikey),
+    Err(ApiKeyError::Missing) => None,
+    Err(_) => return Ok(unauthorized()),
+  };
+  // anonymous callers name the tenant of the flow, api keys always run in their own
+  let (allowed, tenant) = match &apikey {
+    Some(apikey) => (check_access(apikey, kind.name, "run"), tenant_of(apikey)),
+    None => (allows_anonymous(kind, &tenant, &name), tenant),
+  };
+  if !allowed {
+    return Ok(unauthorized());
+  }
+  let db = conn.database(kind.name);
+  let coll = db.collection(kind.name);
+  let metacoll = db.collection(kind.meta);
+  let pquery = query!("name" == name.clone(););
+  let query = scoped(mongo::to_bson(pquery.clone()), &tenant);
   let options = FindOptions::builder()
     .limit(1)
     .build();
@@ -135,58 +463,56 @@ pub fn run_flow(name: String, rev: Option<i64>, data: Json<JsonValue>, conn: Sta
   let meta: Vec<Value> = to_vec!(metacursor);
   
   if meta.len() > 0 {
+    if let Some(apikey) = &apikey {
+      if !acl::allowed(kind.name, Acl::from_value(&meta[0]), apikey, Role::Runner) {
+        return Ok(unauthorized());
+      }
+    }
+    // the trace shows how the flow is built, so only those who may edit it get it
+    let traced = apikey.as_ref().map_or(false, |apikey| check_access(apikey, kind.name, "save")
+      && acl::allowed(kind.name, Acl::from_value(&meta[0]), apikey, Role::Editor));
     let active: i64 = meta[0]["active_rev"].as_i64().unwrap_or(1i64);
     let getrev = rev.unwrap_or(active);
-    let q2 = mongo::to_bson(query!(..pquery && "rev" == getrev));
+    let q2 = scoped(mongo::to_bson(query!(..pquery && "rev" == getrev)), &tenant);
     match coll.find(q2.clone(), Some(options)) {
       Ok(cursor) => {
         let vec: Vec<Value> = to_vec!(cursor);
         if vec.len() > 0 {
           let entry = vec[0].clone();
-          let engine = setup_engine("flows@1.0.0", conn, data.0.into());
-          let nodes = engine.parse_value(entry["flow"].clone()).unwrap();
-          let nnodes = nodes.values().cloned().collect::<Vec<_>>().into_iter();
-          let start_node = nnodes.clone().filter(|n| n.name == "Input").map(|n| n.id).min().unwrap_or(nnodes.map(|n| n.id).min().unwrap());
-      
-          match engine.process(&nodes, start_node) {
-            Ok(output) => {
-              let status = &output["status"].get::<i64>().unwrap();
-              let NodeResult(v) = &output["payload"];
-              let payload = v.get::<Value>().unwrap();
-              Ok(status::Custom(Status::new((**status).try_into().unwrap(), ""), json!({
-                "data": payload,
-                "rev": getrev,
-                "timestamp": current_millis().unwrap()
-              }).into()))      
-            },
-            Err(e) => {
-              Ok(status::Custom(Status::new((500).try_into().unwrap(), ""), json!({
-                "error": format!("{:?}", e),
-                "rev": getrev,
-                "timestamp": current_millis().unwrap()
-              }).into()))  
-            }
-          }
+          let subflows = SubFlows::new(&conn, &policy, Tenant(tenant.clone()), kind, apikey.as_ref()).within(&name);
+          let ctx = subflows.context();
+          let payload: Value = data.0.into();
+
+          let result = process_flow(|| setup_engine(&subflows, payload.clone(), &ctx), &ctx, entry[kind.graph].clone());
+          Ok(run_response(result, getrev, if traced { Some(ctx.trace()) } else { None }))
         } else {
-          Ok(status::Custom(Status::NotFound, json!({}).into()))
+          Ok(not_found(kind, &name))
         }
       },
-      Err(_) => {
-        Ok(status::Custom(Status::InternalServerError, json!({}).into()))
+      Err(e) => {
+        Err(e.into())
       }
     }
   } else {
-    Ok(status::Custom(Status::NotFound, json!({}).into()))
+    Ok(not_found(kind, &name))
   }
 }
 
 #[get("/flows/<name>?<rev>")]
-pub fn get_flow(name: String, rev: Option<i64>, _cookies: Cookies, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
-  let db = conn.database("flows");
-  let coll = db.collection("flows");
-  let metacoll = db.collection("flowsmeta");
-  let pquery = query::parse::from_str(&format!("name == '{}'", name));
-  let query = mongo::to_bson(pquery.clone());
+pub fn get_flow(name: String, rev: Option<i64>, apikey: ApiKey, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  get_doc(FLOWS, name, rev, apikey, conn)
+}
+
+pub fn get_doc(kind: Kind, name: String, rev: Option<i64>, apikey: ApiKey, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  if !check_access(&apikey, kind.name, "read") {
+    return Ok(unauthorized());
+  }
+  let tenant = tenant_of(&apikey);
+  let db = conn.database(kind.name);
+  let coll = db.collection(kind.name);
+  let metacoll = db.collection(kind.meta);
+  let pquery = query!("name" == name.clone(););
+  let query = scoped(mongo::to_bson(pquery.clone()), &tenant);
   let options = FindOptions::builder()
     .limit(1)
     .build();
@@ -195,9 +521,12 @@ pub fn get_flow(name: String, rev: Option<i64>, _cookies: Cookies, conn: State<C
   let meta: Vec<Value> = to_vec!(metacursor);
   
   if meta.len() > 0 {
+    if !acl::allowed(kind.name, Acl::from_value(&meta[0]), &apikey, Role::Editor) {
+      return Ok(unauthorized());
+    }
     let latest: i64 = meta[0]["latest_rev"].as_i64().unwrap_or(1i64);
     let getrev: i64 = rev.unwrap_or(latest);
-    let q2 = mongo::to_bson(query!(..pquery && "rev" == getrev));
+    let q2 = scoped(mongo::to_bson(query!(..pquery && "rev" == getrev)), &tenant);
     match coll.find(q2, Some(options)) {
       Ok(cursor) => {
         let vec: Vec<Value> = to_vec!(cursor);
@@ -208,8 +537,8 @@ pub fn get_flow(name: String, rev: Option<i64>, _cookies: Cookies, conn: State<C
           Ok(status::Custom(Status::Ok, json!({}).into()))
         }
       },
-      Err(_) => {
-        Ok(status::Custom(Status::InternalServerError, json!({}).into()))
+      Err(e) => {
+        Err(e.into())
       }
     }
   } else {
@@ -217,63 +546,309 @@ pub fn get_flow(name: String, rev: Option<i64>, _cookies: Cookies, conn: State<C
   }
 }
 
+#[get("/flows/<name>/revisions")]
+pub fn get_revisions(name: String, apikey: ApiKey, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  get_doc_revisions(FLOWS, name, apikey, conn)
+}
+
+pub fn get_doc_revisions(kind: Kind, name: String, apikey: ApiKey, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  if !check_access(&apikey, kind.name, "read") {
+    return Ok(unauthorized());
+  }
+  let tenant = tenant_of(&apikey);
+  let db = conn.database(kind.name);
+  let coll: Collection = db.collection(kind.name);
+  let metacoll: Collection = db.collection(kind.meta);
+  let query = scoped(mongo::to_bson(query!("name" == name.clone();)), &tenant);
+
+  let meta = match metacoll.find_one(query.clone(), None)? {
+    Some(m) => m,
+    None => return Ok(not_found(kind, &name)),
+  };
+  if !acl::allowed(kind.name, Acl::from_document(&meta), &apikey, Role::Editor) {
+    return Ok(unauthorized());
+  }
+  let active = meta.get_i64("active_rev").unwrap_or(1i64);
+  let latest = meta.get_i64("latest_rev").unwrap_or(1i64);
+
+  let options = FindOptions::builder()
+    .sort(Some(doc!{"rev": 1}))
+    .projection(Some(doc!{"rev": 1, "timestamp": 1, "author": 1, "tag": 1}))
+    .build();
+  let cursor = coll.find(query, Some(options))?;
+  let revisions: Vec<Document> = to_vec!(cursor);
+  let result: Vec<Value> = revisions.iter().map(|r| {
+    let rev = r.get_i64("rev").unwrap_or_default();
+    json!({
+      "rev": rev,
+      "timestamp": r.get_i64("timestamp").ok(),
+      "author": r.get_str("author").ok(),
+      "tag": r.get_str("tag").ok(),
+      "active": rev == active,
+      "latest": rev == latest,
+    })
+  }).collect();
+  Ok(status::Custom(Status::Ok, json!({ "name": name, "active_rev": active, "latest_rev": latest, "revisions": result }).into()))
+}
+
+#[get("/flows/<name>/diff?<from>&<to>")]
+pub fn diff_revisions(name: String, from: Option<i64>, to: Option<i64>, apikey: ApiKey, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  diff_doc_revisions(FLOWS, name, from, to, apikey, conn)
+}
+
+pub fn diff_doc_revisions(kind: Kind, name: String, from: Option<i64>, to: Option<i64>, apikey: ApiKey, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  if !check_access(&apikey, kind.name, "read") {
+    return Ok(unauthorized());
+  }
+  let tenant = tenant_of(&apikey);
+  let db = conn.database(kind.name);
+  let coll: Collection = db.collection(kind.name);
+  let metacoll: Collection = db.collection(kind.meta);
+  let pquery = query!("name" == name.clone(););
+
+  let meta = match metacoll.find_one(scoped(mongo::to_bson(pquery.clone()), &tenant), None)? {
+    Some(m) => m,
+    None => return Ok(not_found(kind, &name)),
+  };
+  if !acl::allowed(kind.name, Acl::from_document(&meta), &apikey, Role::Editor) {
+    return Ok(unauthorized());
+  }
+  let from = from.unwrap_or(meta.get_i64("active_rev").unwrap_or(1i64));
+  let to = to.unwrap_or(meta.get_i64("latest_rev").unwrap_or(1i64));
+
+  let mut graphs = vec![];
+  for &rev in &[from, to] {
+    let revquery = pquery.clone();
+    match coll.find_one(scoped(mongo::to_bson(query!(..revquery && "rev" == rev)), &tenant), None)? {
+      Some(d) => {
+        let entry: Value = bson::from_bson(bson::Bson::Document(d))?;
+        graphs.push(entry[kind.graph].clone());
+      },
+      None => return Ok(ApiError::new(ErrorKind::NotFound, format!("revision {} not found", rev)).into()),
+    }
+  }
+  let result = diff::diff_flows(&graphs[0], &graphs[1]);
+  Ok(status::Custom(Status::Ok, json!({ "name": name, "from": from, "to": to, "diff": result }).into()))
+}
+
+#[get("/flows/<name>/acl")]
+pub fn get_acl(name: String, apikey: ApiKey, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  get_doc_acl(FLOWS, name, apikey, conn)
+}
+
+pub fn get_doc_acl(kind: Kind, name: String, apikey: ApiKey, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  if !check_access(&apikey, kind.name, "read") {
+    return Ok(unauthorized());
+  }
+  let tenant = tenant_of(&apikey);
+  let db = conn.database(kind.name);
+  let metacoll: Collection = db.collection(kind.meta);
+  let query = scoped(mongo::to_bson(query!("name" == name.clone();)), &tenant);
+
+  let meta = match metacoll.find_one(query, None)? {
+    Some(m) => m,
+    None => return Ok(not_found(kind, &name)),
+  };
+  let acl = Acl::from_document(&meta);
+  if !acl::allowed(kind.name, acl.clone(), &apikey, Role::Runner) {
+    return Ok(unauthorized());
+  }
+  Ok(status::Custom(Status::Ok, json!({ "name": name, "acl": acl }).into()))
+}
+
+#[post("/flows/<name>/acl", format="application/json", data="<data>")]
+pub fn set_acl(name: String, data: Json<JsonValue>, apikey: ApiKey, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  set_doc_acl(FLOWS, name, data, apikey, conn)
+}
+
+pub fn set_doc_acl(kind: Kind, name: String, data: Json<JsonValue>, apikey: ApiKey, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  if !check_access(&apikey, kind.name, "save") {
+    return Ok(unauthorized());
+  }
+  let tenant = tenant_of(&apikey);
+  let acl: Acl = match serde_json::from_value(data.0.into()) {
+    Ok(acl) => acl,
+    Err(e) => return Ok(ApiError::new(ErrorKind::BadRequest, e.to_string()).into()),
+  };
+  if acl.owners.is_empty() {
+    return Ok(ApiError::new(ErrorKind::BadRequest, format!("a {} needs at least one owner", kind.graph)).into());
+  }
+  let db = conn.database(kind.name);
+  let metacoll: Collection = db.collection(kind.meta);
+  let query = scoped(mongo::to_bson(query!("name" == name.clone();)), &tenant);
+
+  match metacoll.find_one(query.clone(), None)? {
+    Some(meta) if !acl::allowed(kind.name, Acl::from_document(&meta), &apikey, Role::Owner) => return Ok(unauthorized()),
+    Some(_) => {},
+    None => return Ok(not_found(kind, &name)),
+  }
+  metacoll.update_one(query, doc!("$set": doc!("acl": acl.to_document())), None)?;
+  Ok(status::Custom(Status::Ok, json!({ "name": name, "acl": acl }).into()))
+}
+
 #[post("/flows",format="application/json", data="<data>")]
-pub fn save_flow(data: Json<JsonValue>, _cookies: Cookies, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+pub fn save_flow(data: Json<JsonValue>, apikey: ApiKey, conn: State<Client>, policy: State<MongoPolicy>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  save_doc(FLOWS, data, apikey, conn, policy)
+}
+
+pub fn save_doc(kind: Kind, data: Json<JsonValue>, apikey: ApiKey, conn: State<Client>, policy: State<MongoPolicy>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  if !check_access(&apikey, kind.name, "save") {
+    return Ok(unauthorized());
+  }
+  let tenant = tenant_of(&apikey);
   let name: String = match &data.0["name"] {
     Value::String(n) => n.clone(),
     _ => "_noname".to_string()
   };
-  let db = conn.database("flows");
-  let coll: Collection = db.collection("flows");
-  let metacoll: Collection = db.collection("flowsmeta");
-  let pquery = query::parse::from_str(&format!("name == '{}'", name));
-  let query = mongo::to_bson(pquery);
+  if let Some(response) = reserved_name(kind, &name) {
+    return Ok(response);
+  }
+  let db = conn.database(kind.name);
+  let coll: Collection = db.collection(kind.name);
+  let metacoll: Collection = db.collection(kind.meta);
+  let pquery = query!("name" == name.clone(););
+  let query = scoped(mongo::to_bson(pquery), &tenant);
   
-  let payload_json: Map<String, Value> = serde_json::from_value(data.0["payload"].clone()).unwrap();
-  let payload: Document = Document::try_from(payload_json).unwrap();
-  let flow_json: Map<String, Value> = serde_json::from_value(data.0["flow"].clone()).unwrap();
-  let flow: Document = Document::try_from(flow_json).unwrap();
-
-  let find_options = FindOptions::builder()
-    .sort(Some(doc!{"rev": -1}))
-    .limit(1)
-    .build();
-
-  let meta_options = FindOneAndUpdateOptions::builder()
-    .upsert(true)
-    .build();
+  let payload = object_document(&data.0["payload"], "payload")?;
+  let graph = object_document(&data.0[kind.graph], kind.graph)?;
 
-  let old_cursor = coll.find(query.clone(), Some(find_options))?;
-  let oldvec: Vec<Document> = to_vec!(old_cursor);
-  let last = oldvec.first();
-  let lastrev: i64 = last.map(|d| d.get_i64("rev").unwrap_or(0i64)).unwrap_or(0i64);
-  let nextrev = lastrev + 1;
-  let metaupdate = if nextrev == 1 {
-    doc!("$set": doc!("latest_rev": nextrev, "active_rev": nextrev))
-  } else {
-    doc!("$set": doc!("latest_rev": nextrev))
+  if let Some(meta) = metacoll.find_one(query.clone(), None)? {
+    if !acl::allowed(kind.name, Acl::from_document(&meta), &apikey, Role::Editor) {
+      return Ok(unauthorized());
+    }
+  }
+  // drafts can be saved unchecked with `"validate": false`
+  if data.0["validate"].as_bool().unwrap_or(true) {
+    let issues = validate_graph(kind, &data.0[kind.graph], &conn, &policy, &tenant);
+    if validate::has_errors(&issues) {
+      return Ok(ApiError::new(ErrorKind::InvalidGraph, format!("{} is not valid", kind.graph)).respond(json!({"issues": issues})));
+    }
+  }
+  let base_rev = data.0["base_rev"].as_i64();
+  let nextrev = match next_rev(&metacoll, &name, &tenant, base_rev, &Acl::owned_by(&apikey))? {
+    Some(rev) => rev,
+    None if metacoll.count_documents(query, None)? == 0 => {
+      return Ok(not_found(kind, &name));
+    },
+    None if base_rev.is_some() => {
+      return Ok(ApiError::new(ErrorKind::Conflict, format!("{} has been saved since base_rev", kind.graph)).respond(json!({"base_rev": base_rev})));
+    },
+    // two first saves of the same name raced, the other one created it
+    None => {
+      return Ok(ApiError::new(ErrorKind::Conflict, format!("{} {} was created concurrently, save again", kind.graph, name)).into());
+    },
   };
-  let _res = metacoll.find_one_and_update(query.clone(), metaupdate, meta_options);
-  let res = coll.insert_one(doc!("name": name, "payload": payload, "flow": flow, "rev": nextrev, "timestamp": current_millis().unwrap_or_default()), None);
+  let author = apikey.sub.clone();
+  let tag = data.0["tag"].as_str().map(|t| t.to_string());
+  let mut entry = scoped(doc!("name": name.clone(), "payload": payload, "rev": nextrev, "author": author, "tag": bson_or_null(tag), "timestamp": current_millis().unwrap_or_default()), &tenant);
+  entry.insert(kind.graph, graph);
+  let res = coll.insert_one(entry, None);
   match res {
     Ok(_) => {
+      metacoll.update_one(scoped(doc!("name": name), &tenant), doc!("$max": doc!("latest_rev": nextrev)), None)?;
       Ok(status::Custom(Status::Ok, json!({"rev": nextrev}).into()))
     },
+    Err(e) if is_duplicate_key(&e) => {
+      Ok(ApiError::new(ErrorKind::Conflict, e.to_string()).into())
+    },
     Err(e) => {
-      Ok(status::Custom(Status::InternalServerError, json!({"error": e.to_string()}).into()))
+      Ok(ApiError::new(ErrorKind::Database, e.to_string()).into())
     }
   }
 }
 
+/// Payloads and graphs are stored as documents, so they have to be objects
+fn object_document(value: &Value, field: &str) -> Result<Document, NoCodeError> {
+  match value {
+    Value::Object(map) => Document::try_from(map.clone()).map_err(|e| NoCodeError::InvalidPayload(format!("{} is not valid: {}", field, e))),
+    _ => Err(NoCodeError::InvalidPayload(format!("{} must be an object", field))),
+  }
+}
+
+/// Allocates the next revision number by atomically incrementing `rev_counter`
+/// on the meta document (seeded from `latest_rev` for flows saved before the counter existed).
+/// With a `base_rev` the allocation only succeeds when no other revision was allocated since,
+/// returning `None` otherwise. A new flow gets `owner` as its acl, taken literally
+/// so values starting with `$` are not read as expressions.
+fn next_rev(metacoll: &Collection, name: &str, tenant: &Option<String>, base_rev: Option<i64>, owner: &Acl) -> Result<Option<i64>, NoCodeError> {
+  let current = doc!("$ifNull": ["$rev_counter", doc!("$ifNull": ["$latest_rev", 0i64])]);
+  let filter = match base_rev {
+    Some(base) => scoped(doc!("name": name, "$expr": doc!("$eq": [current.clone(), base])), tenant),
+    None => scoped(doc!("name": name), tenant),
+  };
+  let update = UpdateModifications::Pipeline(vec![
+    doc!("$set": doc!("acl": doc!("$cond": [doc!("$eq": [current.clone(), 0i64]), doc!("$literal": owner.to_document()), "$acl"]))),
+    doc!("$set": doc!("rev_counter": doc!("$add": [current, 1i64]))),
+    doc!("$set": doc!("active_rev": doc!("$ifNull": ["$active_rev", "$rev_counter"]))),
+  ]);
+  let options = FindOneAndUpdateOptions::builder()
+    .upsert(base_rev.map(|b| b == 0).unwrap_or(true))
+    .return_document(ReturnDocument::After)
+    .build();
+  match metacoll.find_one_and_update(filter, update, Some(options)) {
+    Ok(meta) => Ok(meta.and_then(|m| m.get_i64("rev_counter").ok())),
+    // a `base_rev` of 0 upserts, which runs into the unique index when the flow exists
+    Err(e) if is_duplicate_key(&e) => Ok(None),
+    Err(e) => Err(e.into()),
+  }
+}
+
+/// Whether `e` is a violation of a unique index
+fn is_duplicate_key(e: &mongodb::error::Error) -> bool {
+  const DUPLICATE_KEY: i32 = 11000;
+  match e.kind.as_ref() {
+    MongoErrorKind::WriteError(WriteFailure::WriteError(write)) => write.code == DUPLICATE_KEY,
+    MongoErrorKind::CommandError(command) => command.code == DUPLICATE_KEY,
+    _ => false,
+  }
+}
+
+/// Creates the indexes the flow and rule collections rely on
+pub fn ensure_indexes(conn: &Client) -> Result<(), mongodb::error::Error> {
+  for kind in &[FLOWS, RULES] {
+    let db = conn.database(kind.name);
+    // names are only unique within a tenant, replace the global indexes from before tenants
+    let _ = db.run_command(doc!("dropIndexes": kind.name, "index": "name_rev"), None);
+    let _ = db.run_command(doc!("dropIndexes": kind.meta, "index": "name"), None);
+    unique_index(&db, kind.name, doc!("tenant": 1, "name": 1, "rev": 1), "tenant_name_rev")?;
+    unique_index(&db, kind.meta, doc!("tenant": 1, "name": 1), "tenant_name")?;
+  }
+  Ok(())
+}
+
+/// Creates a unique index. Duplicates saved before it existed are reported
+/// instead of keeping the server from starting, the index is built once they are removed.
+fn unique_index(db: &Database, coll: &str, key: Document, name: &str) -> Result<(), mongodb::error::Error> {
+  match db.run_command(doc!("createIndexes": coll, "indexes": [doc!("key": key, "name": name, "unique": true)]), None) {
+    Err(e) if is_duplicate_key(&e) => {
+      println!("could not create index {} on {}.{}: {}", name, db.name(), coll, e);
+      Ok(())
+    },
+    res => res.map(|_| ()),
+  }
+}
+
 #[get("/flows?<limit>&<name>")]
-pub fn get_flows(limit: Option<i64>, name: Option<String>, _cookies: Cookies, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
-  let db = conn.database("flows");
-  let metacoll = db.collection("flowsmeta");
+pub fn get_flows(limit: Option<i64>, name: Option<String>, apikey: ApiKey, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  get_docs(FLOWS, limit, name, apikey, conn)
+}
+
+pub fn get_docs(kind: Kind, limit: Option<i64>, name: Option<String>, apikey: ApiKey, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  if !check_access(&apikey, kind.name, "read") {
+    return Ok(unauthorized());
+  }
+  let tenant = tenant_of(&apikey);
+  let db = conn.database(kind.name);
+  let metacoll = db.collection(kind.meta);
 
   let options = FindOptions::builder()
     .limit(limit.unwrap_or(100i64))
     .build();
-  let query = name.map(|n| mongo::to_bson(query!("name" == n))).unwrap_or(doc!());
+  let mut query = scoped(name.map(|n| mongo::to_bson(query!("name" == n))).unwrap_or_default(), &tenant);
+  // filtered in the query so the limit applies to the flows the caller may see
+  for (key, value) in acl::filter(kind.name, &apikey, Role::Runner) {
+    query.insert(key, value);
+  }
   match metacoll.find(query, Some(options)) {
     Ok(cursor) => {
       let vec: Vec<Value> = to_vec!(cursor);
@@ -284,39 +859,388 @@ pub fn get_flows(limit: Option<i64>, name: Option<String>, _cookies: Cookies, co
         Ok(status::Custom(Status::Ok, json!({}).into()))
       }
     },
-    Err(_) => {
-      Ok(status::Custom(Status::InternalServerError, json!({}).into()))
+    Err(e) => {
+      Err(e.into())
     }
   }
 }
 
+#[get("/nodes")]
+pub fn get_nodes(apikey: ApiKey, conn: State<Client>, policy: State<MongoPolicy>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  if !check_access(&apikey, FLOWS.name, "read") && !check_access(&apikey, RULES.name, "read") {
+    return Ok(unauthorized());
+  }
+  let registry = registry(&SubFlows::new(&conn, &policy, Tenant(tenant_of(&apikey)), FLOWS, None), Value::Null, &FlowContext::new());
+  Ok(status::Custom(Status::Ok, json!(registry.specs()).into()))
+}
+
+#[post("/flows/validate", format="application/json", data="<data>")]
+pub fn validate_flow(data: Json<Value>, apikey: ApiKey, conn: State<Client>, policy: State<MongoPolicy>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  validate_doc(FLOWS, data, apikey, conn, policy)
+}
+
+/// Reports what is wrong with a graph without saving or running it.
+/// Takes the same body as a save, or the bare graph.
+pub fn validate_doc(kind: Kind, data: Json<Value>, apikey: ApiKey, conn: State<Client>, policy: State<MongoPolicy>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  if !check_access(&apikey, kind.name, "read") && !check_access(&apikey, kind.name, "save") {
+    return Ok(unauthorized());
+  }
+  let graph = match &data.0[kind.graph] {
+    Value::Null => &data.0,
+    graph => graph,
+  };
+  let issues = validate_graph(kind, graph, &conn, &policy, &tenant_of(&apikey));
+  Ok(status::Custom(Status::Ok, json!({"valid": !validate::has_errors(&issues), "issues": issues}).into()))
+}
+
+fn validate_graph(kind: Kind, graph: &Value, conn: &Client, policy: &MongoPolicy, tenant: &Option<String>) -> Vec<validate::Issue> {
+  let registry = registry(&SubFlows::new(conn, policy, Tenant(tenant.clone()), kind, None), Value::Null, &FlowContext::new());
+  validate::validate(graph, registry.specs())
+}
+
 #[post("/flowtest",format="application/json", data="<data>")]
-pub fn test_flow(data: Json<Value>, _cookies: Cookies, conn: State<Client>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+pub fn test_flow(data: Json<Value>, apikey: ApiKey, conn: State<Client>, policy: State<MongoPolicy>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  test_doc(FLOWS, data, apikey, conn, policy)
+}
+
+pub fn test_doc(kind: Kind, data: Json<Value>, apikey: ApiKey, conn: State<Client>, policy: State<MongoPolicy>) -> Result<status::Custom<JsonValue>, NoCodeError> {
+  if !check_access(&apikey, kind.name, "test") {
+    return Ok(unauthorized());
+  }
+  let tenant = tenant_of(&apikey);
   let payload = data.0["payload"].clone();
-  let flow = data.0["flow"].clone();
-  let engine = setup_engine("flows@1.0.0", conn, payload);
-  let nodes = engine.parse_value(flow).unwrap();
-  let nnodes = nodes.values().cloned().collect::<Vec<_>>().into_iter();
-  let start_node = nnodes.clone().filter(|n| n.name == "Input").map(|n| n.id).min().unwrap_or(nnodes.map(|n| n.id).min().unwrap());
-
-  match engine.process(&nodes, start_node) {
-    Ok(output) => {
-      let status = &output["status"].get::<i64>().unwrap();
-      let NodeResult(v) = &output["payload"];
-      let payload = v.get::<Value>().unwrap();
-      Ok(status::Custom(Status::new((**status).try_into().unwrap(), ""), json!({
-        "data": payload,
-        "rev": -1,
-        "timestamp": current_millis().unwrap()
-      }).into()))    
-    },
-    Err(e) => {
-      println!("{}", &e);
-      Ok(status::Custom(Status::new((200).try_into().unwrap(), ""), json!({
-        "error": format!("{:?}", e),
-        "rev": -1,
-        "timestamp": current_millis().unwrap()
-      }).into()))  
+  let flow = data.0[kind.graph].clone();
+  let subflows = SubFlows::new(&conn, &policy, Tenant(tenant), kind, Some(&apikey));
+  let ctx = subflows.context();
+
+  let result = process_flow(|| setup_engine(&subflows, payload.clone(), &ctx), &ctx, flow);
+  Ok(run_response(result, -1, Some(ctx.trace())))
+}
+
+#[cfg(test)]
+mod nocode_test {
+  use std::cell::Cell;
+  use d3ne::*;
+  use rocket::http::Status;
+  use serde_json::Value;
+  use bson::{Bson, Document};
+  use mongodb::sync::{Client, Collection};
+  use rocket::State;
+  use rocket_contrib::json::Json;
+  use crate::apikey::ApiKey;
+  use crate::nocode::{clone_doc, next_rev, nodes, object_document, process_flow, rename_doc, reserved_name, run_response, set_active_doc, unique_index, NoCodeError, FLOWS};
+  use crate::nocode::acl::Acl;
+  use crate::nocode::catalog::{Describe, NodeSpec, Registry, SocketType};
+  use crate::nocode::error::{ApiError, ErrorKind};
+  use crate::nocode::flow::FlowContext;
+  use crate::nocode::retry::RetryBudget;
+  use crate::util::bson_or_null;
+  use crate::util::test::{apikey, passthrough};
+
+  fn run_with(graph: Value, payload: Value) -> Result<d3ne::OutputData, NoCodeError> {
+    let ctx = FlowContext::new();
+    let engine = || {
+      let mut registry = Registry::new(&ctx);
+      registry.add(nodes::Input(payload.clone()))
+        .add(nodes::Output)
+        .add(nodes::Number)
+        .add(nodes::Nth);
+      registry.engine("flows@1.0.0")
+    };
+    process_flow(engine, &ctx, graph)
+  }
+
+  fn run(graph: Value) -> Result<d3ne::OutputData, NoCodeError> {
+    run_with(graph, json!({"a": 1}))
+  }
+
+  fn graph(status: i64, output: bool) -> Value {
+    let mut graph = passthrough(status);
+    if !output {
+      if let Some(nodes) = graph["nodes"].as_object_mut() {
+        nodes.remove("2");
+      }
+      graph["nodes"]["1"]["outputs"]["payload"]["connections"] = json!([]);
+    }
+    graph
+  }
+
+  #[test]
+  fn runs_flow() {
+    let response = run_response(run(graph(201, true)), 1, None);
+    assert_eq!(response.0, Status::Created);
+    assert_eq!((response.1).0["data"], json!({"a": 1}));
+    assert!((response.1).0.get("trace").is_none());
+    assert_eq!((run_response(run(graph(201, true)), 1, Some(vec![])).1).0["trace"], json!([]));
+  }
+
+  #[test]
+  fn empty_flow() {
+    assert!(matches!(run(json!({"id": "flows@1.0.0", "nodes": {}})), Err(NoCodeError::InvalidGraph(_))));
+    assert_eq!(run_response(run(json!({"id": "flows@1.0.0", "nodes": {}})), 1, None).0, Status::UnprocessableEntity);
+  }
+
+  #[test]
+  fn missing_output() {
+    assert!(matches!(run(graph(200, false)), Err(NoCodeError::MissingOutput)));
+  }
+
+  #[test]
+  fn out_of_range_status() {
+    let response = run_response(run(graph(1000, true)), 1, None);
+    assert_eq!(response.0, Status::UnprocessableEntity);
+    assert_eq!((response.1).0["error"]["input"], json!("status"));
+    assert_eq!(run_response(run(graph(-1, true)), 1, None).0, Status::UnprocessableEntity);
+  }
+
+  #[test]
+  fn nth_out_of_range() {
+    let graph = json!({"id": "flows@1.0.0", "nodes": {
+      "1": { "id": 1, "name": "Input", "data": {}, "inputs": {}, "position": [0, 0],
+        "outputs": { "payload": { "connections": [{ "node": 3, "input": "payload", "data": {} }] } } },
+      "3": { "id": 3, "name": "Nth", "data": { "nth": 5 }, "position": [0, 0],
+        "inputs": { "payload": { "connections": [{ "node": 1, "output": "payload", "data": {} }] } },
+        "outputs": { "json": { "connections": [{ "node": 2, "input": "payload", "data": {} }] } } },
+      "2": { "id": 2, "name": "Output", "data": { "status": 200 }, "outputs": {}, "position": [0, 0],
+        "inputs": { "payload": { "connections": [{ "node": 3, "output": "json", "data": {} }] } } }
+    }});
+    match run_with(graph, json!([1, 2])) {
+      Err(NoCodeError::Run(e)) => {
+        assert_eq!(e.node, Some(3));
+        assert_eq!(e.input.as_deref(), Some("nth"));
+      },
+      _ => panic!("expected Nth to fail"),
+    }
+  }
+
+  /// Passes its payload on once it failed `fails` times with a database error
+  struct Flaky(Cell<u32>, u32);
+  impl Worker for Flaky {
+    fn name(&self) -> &str {
+      "Flaky"
+    }
+    fn work(&self, node: &Node, input_data: InputData) -> anyhow::Result<OutputData> {
+      if self.0.get() < self.1 {
+        self.0.set(self.0.get() + 1);
+        return Err(ApiError::new(ErrorKind::Database, "connection reset").into());
+      }
+      let payload = node.get_json_field("payload", &input_data)?;
+      Ok(OutputDataBuilder::new().data("payload", Box::new(payload)).build())
+    }
+  }
+  impl Describe for Flaky {
+    fn describe(&self) -> NodeSpec {
+      NodeSpec::new("Flaky").input("payload", SocketType::Json).output("payload", SocketType::Json)
+    }
+  }
+
+  #[test]
+  fn retries_transient_errors() {
+    let graph = json!({"id": "flows@1.0.0", "nodes": {
+      "1": { "id": 1, "name": "Input", "data": {}, "inputs": {}, "position": [0, 0],
+        "outputs": { "payload": { "connections": [{ "node": 3, "input": "payload", "data": {} }] } } },
+      "3": { "id": 3, "name": "Flaky", "data": { "retry": { "max_attempts": 3, "backoff_ms": 1 } }, "position": [0, 0],
+        "inputs": { "payload": { "connections": [{ "node": 1, "output": "payload", "data": {} }] } },
+        "outputs": { "payload": { "connections": [{ "node": 2, "input": "payload", "data": {} }] } } },
+      "2": { "id": 2, "name": "Output", "data": { "status": 200 }, "outputs": {}, "position": [0, 0],
+        "inputs": { "payload": { "connections": [{ "node": 3, "output": "payload", "data": {} }] } } }
+    }});
+    // failures before success, wait budget in ms, attempts made, whether the run succeeds
+    for &(fails, budget, attempts, ok) in &[(2, 1000, 3, true), (3, 1000, 3, false), (1, 0, 1, false)] {
+      let ctx = FlowContext::with_budget(RetryBudget::new(budget));
+      let engine = || {
+        let mut registry = Registry::new(&ctx);
+        registry.add(nodes::Input(json!({"a": 1})))
+          .add(nodes::Output)
+          .add(Flaky(Cell::new(0), fails));
+        registry.engine("flows@1.0.0")
+      };
+      assert_eq!(process_flow(engine, &ctx, graph.clone()).is_ok(), ok);
+      let step = ctx.trace().into_iter().find(|s| s.node == 3).unwrap();
+      assert_eq!(step.attempts, attempts);
+      assert_eq!(step.error, if ok { None } else { Some(ErrorKind::Database) });
+    }
+  }
+
+  #[test]
+  fn non_object_payload() {
+    assert!(object_document(&json!({"a": 1}), "payload").is_ok());
+    for payload in &[json!([1, 2]), json!("text"), json!(null)] {
+      assert!(matches!(object_document(payload, "payload"), Err(NoCodeError::InvalidPayload(_))));
+    }
+  }
+
+  #[test]
+  fn reserves_endpoint_names() {
+    assert_eq!(reserved_name(FLOWS, "validate").map(|r| r.0), Some(Status::BadRequest));
+    assert!(reserved_name(FLOWS, "_validate").is_none());
+  }
+
+  /// A client of the MongoDB at `DB_CONN_STRING`
+  fn client() -> Client {
+    let uri = std::env::var("DB_CONN_STRING").unwrap_or("mongodb://localhost:27017".to_string());
+    Client::with_uri_str(&uri).unwrap()
+  }
+
+  /// A meta collection of its own
+  fn meta_collection() -> Collection {
+    let db = client().database("nocode_test");
+    let name = format!("meta_{}", uuid::Uuid::new_v4().to_simple());
+    unique_index(&db, &name, doc!("tenant": 1, "name": 1), "tenant_name").unwrap();
+    db.collection(&name)
+  }
+
+  #[test]
+  #[ignore = "needs a MongoDB"]
+  fn allocates_revisions() {
+    let metacoll = meta_collection();
+    let owner = Acl { owners: vec!["alice".to_string()], ..Acl::default() };
+    let other = Acl { owners: vec!["bob".to_string()], ..Acl::default() };
+    // saves without base_rev create the flow, then count up
+    assert_eq!(next_rev(&metacoll, "a", &None, None, &owner).unwrap(), Some(1));
+    assert_eq!(next_rev(&metacoll, "a", &None, None, &other).unwrap(), Some(2));
+    let meta = metacoll.find_one(doc!("name": "a"), None).unwrap().unwrap();
+    assert_eq!(meta.get_document("acl").unwrap(), &owner.to_document());
+    assert_eq!(meta.get_i64("active_rev").unwrap(), 1);
+    // the same name in another tenant is another flow
+    assert_eq!(next_rev(&metacoll, "a", &Some("t".to_string()), None, &owner).unwrap(), Some(1));
+    metacoll.drop(None).unwrap();
+  }
+
+  #[test]
+  #[ignore = "needs a MongoDB"]
+  fn checks_base_rev() {
+    let metacoll = meta_collection();
+    let owner = Acl { owners: vec!["alice".to_string()], ..Acl::default() };
+    // a base_rev of 0 creates the flow, but only once
+    assert_eq!(next_rev(&metacoll, "a", &None, Some(0), &owner).unwrap(), Some(1));
+    assert_eq!(next_rev(&metacoll, "a", &None, Some(0), &owner).unwrap(), None);
+    assert_eq!(next_rev(&metacoll, "a", &None, Some(1), &owner).unwrap(), Some(2));
+    // saved since base_rev
+    assert_eq!(next_rev(&metacoll, "a", &None, Some(1), &owner).unwrap(), None);
+    // other base_revs never create a flow
+    assert_eq!(next_rev(&metacoll, "b", &None, Some(3), &owner).unwrap(), None);
+    assert_eq!(metacoll.count_documents(doc!("name": "b"), None).unwrap(), 0);
+    metacoll.drop(None).unwrap();
+  }
+
+  #[test]
+  #[ignore = "needs a MongoDB"]
+  fn continues_from_latest_rev() {
+    let metacoll = meta_collection();
+    let owner = Acl { owners: vec!["alice".to_string()], ..Acl::default() };
+    // flows saved before the counter existed only have latest_rev
+    metacoll.insert_one(doc!("name": "a", "tenant": Bson::Null, "latest_rev": 4i64, "active_rev": 2i64), None).unwrap();
+    assert_eq!(next_rev(&metacoll, "a", &None, Some(4), &owner).unwrap(), Some(5));
+    let meta = metacoll.find_one(doc!("name": "a"), None).unwrap().unwrap();
+    assert_eq!(meta.get_i64("active_rev").unwrap(), 2);
+    assert!(meta.get_document("acl").is_err());
+    metacoll.drop(None).unwrap();
+  }
+
+  /// An editor in a tenant of its own, owning flow `a` with revisions 1 to 3,
+  /// the first two tagged `v1`
+  fn seeded(conn: &Client, active_rev: Option<i64>) -> ApiKey {
+    let tenant = uuid::Uuid::new_v4().to_simple().to_string();
+    let db = conn.database(FLOWS.name);
+    let mut meta = doc!("name": "a", "tenant": tenant.clone(), "latest_rev": 3i64, "rev_counter": 3i64,
+      "acl": {"owners": ["alice"], "editors": [], "runners": []});
+    if let Some(rev) = active_rev {
+      meta.insert("active_rev", rev);
+    }
+    db.collection(FLOWS.meta).insert_one(meta, None).unwrap();
+    for &(rev, tag) in &[(1i64, Some("v1")), (2, Some("v1")), (3, None)] {
+      db.collection(FLOWS.name).insert_one(doc!("name": "a", "tenant": tenant.clone(), "rev": rev, "tag": bson_or_null(tag), "flow": {}), None).unwrap();
+    }
+    apikey("alice", json!({ "entities": [tenant], "scopes": [{ "scope": "flows", "actions": ["read", "save"] }] }))
+  }
+
+  fn meta_of(conn: &Client, apikey: &ApiKey, name: &str) -> Option<Document> {
+    conn.database(FLOWS.name).collection(FLOWS.meta).find_one(doc!("name": name, "tenant": apikey.entities[0].clone()), None).unwrap()
+  }
+
+  fn revision_of(conn: &Client, apikey: &ApiKey, name: &str, rev: i64) -> Option<Document> {
+    conn.database(FLOWS.name).collection(FLOWS.name).find_one(doc!("name": name, "tenant": apikey.entities[0].clone(), "rev": rev), None).unwrap()
+  }
+
+  fn clean_up(conn: &Client, apikey: &ApiKey) {
+    let db = conn.database(FLOWS.name);
+    for coll in &[FLOWS.name, FLOWS.meta] {
+      db.collection(coll).delete_many(doc!("tenant": apikey.entities[0].clone()), None).unwrap();
     }
   }
-}
\ No newline at end of file
+
+  #[test]
+  #[ignore = "needs a MongoDB"]
+  fn renames_flows() {
+    let rocket = rocket::ignite().manage(client());
+    let conn = State::<Client>::from(&rocket).unwrap();
+    let apikey = seeded(&conn, Some(1));
+    let rename = |name: &str, body: Value| rename_doc(FLOWS, name.to_string(), Json(body.into()), apikey.clone(), State::from(&rocket).unwrap()).unwrap();
+
+    assert_eq!(rename("a", json!({"name": "validate"})).0, Status::BadRequest);
+    let res = rename("a", json!({"name": "b"}));
+    assert_eq!(res.0, Status::Ok);
+    assert_eq!((res.1).0["revs"], json!(3));
+    assert!(meta_of(&conn, &apikey, "a").is_none());
+    assert!(revision_of(&conn, &apikey, "b", 3).is_some());
+    assert_eq!(rename("a", json!({"name": "c"})).0, Status::NotFound);
+    clean_up(&conn, &apikey);
+  }
+
+  #[test]
+  #[ignore = "needs a MongoDB"]
+  fn clones_flows() {
+    let rocket = rocket::ignite().manage(client());
+    let conn = State::<Client>::from(&rocket).unwrap();
+    let apikey = seeded(&conn, Some(1));
+    let clone = |body: Value| clone_doc(FLOWS, "a".to_string(), Json(body.into()), apikey.clone(), State::from(&rocket).unwrap()).unwrap();
+
+    let res = clone(json!({"name": "b", "rev": 1}));
+    assert_eq!(res.0, Status::Ok);
+    assert_eq!((res.1).0["cloned_from"], json!({"name": "a", "rev": 1}));
+    let entry = revision_of(&conn, &apikey, "b", 1).unwrap();
+    assert_eq!(entry.get_str("tag").unwrap(), "v1");
+    assert_eq!(meta_of(&conn, &apikey, "b").unwrap().get_i64("active_rev").unwrap(), 1);
+    // the latest revision unless one is asked for
+    assert_eq!((clone(json!({"name": "c"})).1).0["cloned_from"]["rev"], json!(3));
+    assert_eq!(clone(json!({"name": "b"})).0, Status::Conflict);
+    assert_eq!(clone(json!({"name": "d", "rev": 9})).0, Status::NotFound);
+    clean_up(&conn, &apikey);
+  }
+
+  #[test]
+  #[ignore = "needs a MongoDB"]
+  fn activates_by_tag_and_previous() {
+    let rocket = rocket::ignite().manage(client());
+    let conn = State::<Client>::from(&rocket).unwrap();
+    let apikey = seeded(&conn, Some(1));
+    let activate = |body: Value| set_active_doc(FLOWS, "a".to_string(), Json(body.into()), apikey.clone(), State::from(&rocket).unwrap()).unwrap();
+
+    // the newest revision with the tag
+    let res = activate(json!({"tag": "v1"}));
+    assert_eq!((res.1).0["new_active"], json!(2));
+    assert_eq!((res.1).0["previous_active"], json!(1));
+    assert_eq!((activate(json!({"rev": "latest"})).1).0["new_active"], json!(3));
+    assert_eq!((activate(json!({"rev": "previous"})).1).0["new_active"], json!(2));
+    assert_eq!(meta_of(&conn, &apikey, "a").unwrap().get_array("activations").unwrap().len(), 3);
+    assert_eq!(activate(json!({"tag": "v9"})).0, Status::NotFound);
+    assert_eq!(activate(json!({"rev": true})).0, Status::BadRequest);
+    clean_up(&conn, &apikey);
+  }
+
+  #[test]
+  #[ignore = "needs a MongoDB"]
+  fn activates_without_active_rev() {
+    let rocket = rocket::ignite().manage(client());
+    let conn = State::<Client>::from(&rocket).unwrap();
+    let apikey = seeded(&conn, None);
+
+    let res = set_active_doc(FLOWS, "a".to_string(), Json(json!({"rev": 2}).into()), apikey.clone(), State::from(&rocket).unwrap()).unwrap();
+    assert_eq!(res.0, Status::Ok);
+    // revision 1 counts as active before the first activation
+    assert_eq!((res.1).0["previous_active"], json!(1));
+    assert_eq!(meta_of(&conn, &apikey, "a").unwrap().get_i64("active_rev").unwrap(), 2);
+    clean_up(&conn, &apikey);
+  }
+}
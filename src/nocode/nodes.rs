@@ -1,18 +1,30 @@
 use js_sandbox::AnyError;
-use mongodb::options::FindOptions;
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument, UpdateOptions};
+use bson::Bson;
 use serde_json::Value;
 use mongodb::sync::Client;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::rc::Rc;
 use anyhow::Result;
 
-use regex::Regex;
 use js_sandbox::Script;
 use handlebars::Handlebars;
 
 use d3ne::*;
 use querylib::{mongo, query, query::*};
 
+use crate::nocode::condition;
+use crate::nocode::error::{invalid_input, ApiError, ErrorKind};
+use crate::nocode::catalog::{Describe, NodeSpec, SocketType};
+use crate::util::current_millis;
+use crate::nocode::flow::FlowContext;
+use crate::nocode::policy::{Access, MongoPolicy, Tenant};
+use crate::nocode::subflow::SubFlows;
+
+/// Threads a ForEach may spread its elements over
+const MAX_CONCURRENCY: u64 = 16;
+
 fn fix_empty_string(s: String) -> Option<String> {
   if s == "".to_string() { None } else { Some(s) }
 }
@@ -27,74 +39,118 @@ impl Worker for Number {
       "Number"
   }
   fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
-    let result = node.get_number_field("num", &input_data)?;
+    let result = node.get_number_field("num", &input_data).map_err(invalid_input("num"))?;
     Ok(OutputDataBuilder::new()
       .data("num", Box::new(result))
       .build())
   }
 }
+impl Describe for Number {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .control("num", json!(0))
+      .output("num", SocketType::Number)
+  }
+}
 pub struct Float;
 impl Worker for Float {
   fn name(&self) -> &str {
       "Float"
   }
   fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
-    let result = node.get_float_number_field("float", &input_data)?;
+    let result = node.get_float_number_field("float", &input_data).map_err(invalid_input("float"))?;
     Ok(OutputDataBuilder::new()
       .data("float", Box::new(result))
       .build())        
   }
 }
+impl Describe for Float {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .control("float", json!(0.0))
+      .output("float", SocketType::Float)
+  }
+}
 pub struct Text;
 impl Worker for Text {
   fn name(&self) -> &str {
       "Text"
   }
   fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
-    let result = node.get_string_field("txt", &input_data)?;
+    let result = node.get_string_field("txt", &input_data).map_err(invalid_input("txt"))?;
     Ok(OutputDataBuilder::new()
       .data("txt", Box::new(result))
       .build())  
   }
 }
+impl Describe for Text {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .control("txt", json!(""))
+      .output("txt", SocketType::String)
+  }
+}
 pub struct Template;
 impl Worker for Template {
   fn name(&self) -> &str {
       "Template"
   }
   fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
-    let result = node.get_string_field("template", &input_data)?;  
+    let result = node.get_string_field("template", &input_data).map_err(invalid_input("template"))?;  
     Ok(OutputDataBuilder::new()
       .data("template", Box::new(result))
       .build())
   }
 }
+impl Describe for Template {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .control("template", json!(""))
+      .output("template", SocketType::Template)
+  }
+}
 pub struct Add;
 impl Worker for Add {
   fn name(&self) -> &str {
       "Add"
   }
   fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
-    let num = node.get_number_field("num", &input_data)?;
-    let num2 = node.get_number_field("num2", &input_data)?;
+    let num = node.get_number_field("num", &input_data).map_err(invalid_input("num"))?;
+    let num2 = node.get_number_field("num2", &input_data).map_err(invalid_input("num2"))?;
     Ok(OutputDataBuilder::new()
       .data("num", Box::new(num + num2))
       .build())  
   }
 }
+impl Describe for Add {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .input("num", SocketType::Number)
+      .input("num2", SocketType::Number)
+      .output("num", SocketType::Number)
+  }
+}
 pub struct Multiply;
 impl Worker for Multiply {
   fn name(&self) -> &str {
       "Multiply"
   }
   fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
-    let num = node.get_number_field("num", &input_data)?;
-    let num2 = node.get_number_field("num2", &input_data)?;
+    let num = node.get_number_field("num", &input_data).map_err(invalid_input("num"))?;
+    let num2 = node.get_number_field("num2", &input_data).map_err(invalid_input("num2"))?;
     Ok(OutputDataBuilder::new()
       .data("num", Box::new(num * num2))
       .build())        
   }
 }
+impl Describe for Multiply {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .input("num", SocketType::Number)
+      .input("num2", SocketType::Number)
+      .output("num", SocketType::Number)
+  }
+}
 pub struct JsonData;
 impl Worker for JsonData {
   fn name(&self) -> &str {
@@ -107,6 +163,13 @@ impl Worker for JsonData {
       .build())
   }
 }
+impl Describe for JsonData {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .control("json", json!({}))
+      .output("json", SocketType::Json)
+  }
+}
 pub struct ToJson;
 impl Worker for ToJson {
   fn name(&self) -> &str {
@@ -114,7 +177,7 @@ impl Worker for ToJson {
   }
   fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
     let name = node.data.as_ref().and_then(|d| d["name"].as_str()).and_then(fix_empty_str).unwrap_or("data");
-    let data = node.get_as_json_field("data", &input_data)?;
+    let data = node.get_as_json_field("data", &input_data).map_err(invalid_input("data"))?;
     let new_json = match &data {
       Value::String(s) => format!("{{ \"{}\" : \"{}\" }}", name, s),
       _ => format!("{{ \"{}\" : {} }}", name, serde_json::to_string(&data).unwrap())
@@ -125,13 +188,21 @@ impl Worker for ToJson {
       .build())        
   }
 }
+impl Describe for ToJson {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .input("data", SocketType::Any)
+      .control("name", json!("data"))
+      .output("json", SocketType::Json)
+  }
+}
 pub struct ToFloat;
 impl Worker for ToFloat {
   fn name(&self) -> &str {
       "ToFloat"
   }
   fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
-    let data = node.get_as_json_field("data", &input_data)?;
+    let data = node.get_as_json_field("data", &input_data).map_err(invalid_input("data"))?;
     let float: f64 = match &data {
       Value::String(s) => s.parse::<f64>().unwrap_or(0f64),
       Value::Number(n) => n.as_f64().unwrap_or(0f64),
@@ -143,13 +214,20 @@ impl Worker for ToFloat {
       .build())  
   }
 }
+impl Describe for ToFloat {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .input("data", SocketType::Any)
+      .output("float", SocketType::Float)
+  }
+}
 pub struct ToNumber;
 impl Worker for ToNumber {
   fn name(&self) -> &str {
       "ToNumber"
   }
   fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
-    let data = node.get_as_json_field("data", &input_data)?;
+    let data = node.get_as_json_field("data", &input_data).map_err(invalid_input("data"))?;
     let num: i64 = match &data {
       Value::String(s) => s.parse::<f64>().unwrap_or(0f64) as i64,
       Value::Number(n) => n.as_f64().unwrap_or(0f64) as i64,
@@ -161,13 +239,20 @@ impl Worker for ToNumber {
       .build())  
   }
 }
+impl Describe for ToNumber {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .input("data", SocketType::Any)
+      .output("num", SocketType::Number)
+  }
+}
 pub struct ToText;
 impl Worker for ToText {
   fn name(&self) -> &str {
       "ToText"
   }
   fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
-    let data = node.get_as_json_field("data", &input_data)?;
+    let data = node.get_as_json_field("data", &input_data).map_err(invalid_input("data"))?;
     let text: String = match &data {
       Value::String(s) => s.clone(),
       Value::Number(n) => n.as_f64().unwrap_or(0f64).to_string(),
@@ -179,6 +264,13 @@ impl Worker for ToText {
       .build())      
   }
 }
+impl Describe for ToText {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .input("data", SocketType::Any)
+      .output("txt", SocketType::String)
+  }
+}
 pub struct ArrayMap;
 impl Worker for ArrayMap {
   fn name(&self) -> &str {
@@ -207,6 +299,15 @@ impl Worker for ArrayMap {
       .build())
   }
 }
+impl Describe for ArrayMap {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .input("payload", SocketType::Json)
+      .input("fields", SocketType::String)
+      .control("fields", json!(""))
+      .output("json", SocketType::Json)
+  }
+}
 pub struct ArraySum;
 impl Worker for ArraySum {
   fn name(&self) -> &str {
@@ -222,13 +323,21 @@ impl Worker for ArraySum {
       .build())
   }
 }
+impl Describe for ArraySum {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .input("payload", SocketType::Json)
+      .input("field", SocketType::String)
+      .output("float", SocketType::Float)
+  }
+}
 pub struct ArrayCount;
 impl Worker for ArrayCount {
   fn name(&self) -> &str {
       "Array Count"
   }
   fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
-    let data = node.get_json_field("payload", &input_data)?;
+    let data = node.get_json_field("payload", &input_data).map_err(invalid_input("payload"))?;
     let arr: Vec<Value> = serde_json::from_value(data)?;
     let count = arr.len() as i64;
     Ok(OutputDataBuilder::new()
@@ -236,13 +345,91 @@ impl Worker for ArrayCount {
       .build())
   }
 }
-pub struct ArrayFlattern;
-impl Worker for ArrayFlattern {
+impl Describe for ArrayCount {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .input("payload", SocketType::Json)
+      .output("num", SocketType::Number)
+  }
+}
+/// Runs a nested graph once per element of `payload`, either the graph kept in `flow`
+/// or the stored flow named in `name` (pinned to `rev` when set). Its Input gets
+/// `{"element": ..., "index": ...}` and the payloads of its Output are collected in `json`.
+pub struct ForEach(pub SubFlows);
+impl Worker for ForEach {
   fn name(&self) -> &str {
-      "Array Flattern"
+      "ForEach"
   }
   fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
-    let data = node.get_json_field("payload", &input_data)?;
+    let elements = match node.get_json_field("payload", &input_data).map_err(invalid_input("payload"))? {
+      Value::Array(elements) => elements,
+      _ => bail!(ApiError::new(ErrorKind::InvalidInput, "payload is not an array").input("payload")),
+    };
+    let settings = node.data.clone().unwrap_or_default();
+    let concurrency = settings["concurrency"].as_u64().unwrap_or(1).min(MAX_CONCURRENCY) as usize;
+    let ordered = settings["ordered"].as_bool().unwrap_or(true);
+    let results = match settings["name"].as_str().and_then(fix_empty_str) {
+      Some(name) => {
+        let (graph, nested) = self.0.enter(name, settings["rev"].as_i64().filter(|rev| *rev > 0)).map_err(ApiError::from)?;
+        nested.for_each(&graph, elements, concurrency, ordered)
+      },
+      None => self.0.for_each(&settings["flow"], elements, concurrency, ordered),
+    }.map_err(ApiError::from)?;
+    Ok(OutputDataBuilder::new()
+      .data("json", Box::new(Value::Array(results)))
+      .build())
+  }
+}
+impl Describe for ForEach {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .action()
+      .input("payload", SocketType::Json)
+      .control("flow", json!({}))
+      .control("name", json!(""))
+      .control("rev", Value::Null)
+      .control("concurrency", json!(1))
+      .control("ordered", json!(true))
+      .output("json", SocketType::Json)
+  }
+}
+/// Runs the stored flow named in `name`, its active revision unless `rev` pins one,
+/// with `payload` as its input and passes on the payload and status of its Output
+pub struct CallFlow(pub SubFlows);
+impl Worker for CallFlow {
+  fn name(&self) -> &str {
+      "Call Flow"
+  }
+  fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
+    let name = node.get_string_field("name", &input_data).map_err(invalid_input("name"))?;
+    let payload = node.get_as_json_field_or("payload", &input_data, Some(json!({}))).map_err(invalid_input("payload"))?;
+    // the editor leaves 0 in an unset revision
+    let rev = node.data.as_ref().and_then(|d| d["rev"].as_i64()).filter(|rev| *rev > 0);
+    let (status, payload) = self.0.call(&name, rev, payload).map_err(ApiError::from)?;
+    Ok(OutputDataBuilder::new()
+      .data("payload", Box::new(payload))
+      .data("status", Box::new(i64::from(status.code)))
+      .build())
+  }
+}
+impl Describe for CallFlow {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .action()
+      .optional("payload", SocketType::Json)
+      .control("name", json!(""))
+      .control("rev", Value::Null)
+      .output("payload", SocketType::Json)
+      .output("status", SocketType::Number)
+  }
+}
+pub struct ArrayFlatten;
+impl Worker for ArrayFlatten {
+  fn name(&self) -> &str {
+      "Array Flatten"
+  }
+  fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
+    let data = node.get_json_field("payload", &input_data).map_err(invalid_input("payload"))?;
     let arr: Vec<Vec<Value>> = serde_json::from_value(data)?;
     let res: Vec<Value> = arr.into_iter().flatten().collect();
     Ok(OutputDataBuilder::new()
@@ -250,18 +437,34 @@ impl Worker for ArrayFlattern {
       .build())  
   }
 }
+impl Describe for ArrayFlatten {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .input("payload", SocketType::Json)
+      .output("json", SocketType::Json)
+  }
+}
 pub struct Head;
 impl Worker for Head {
   fn name(&self) -> &str {
       "Head"
   }
   fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
-    let data = node.get_json_field("payload", &input_data)?;
+    let data = node.get_json_field("payload", &input_data).map_err(invalid_input("payload"))?;
+    let head = data.as_array().and_then(|a| a.first())
+      .ok_or_else(|| ApiError::new(ErrorKind::InvalidInput, "payload is not an array with at least one element").input("payload"))?;
     Ok(OutputDataBuilder::new()
-      .data("json", Box::new(data[0].clone()))
+      .data("json", Box::new(head.clone()))
       .build())
   }
 }
+impl Describe for Head {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .input("payload", SocketType::Json)
+      .output("json", SocketType::Json)
+  }
+}
 pub struct Nth;
 impl Worker for Nth {
   fn name(&self) -> &str {
@@ -269,11 +472,23 @@ impl Worker for Nth {
   }
   fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
     let (data, nth) =  nth_values(node, input_data)?;
+    let array = data.as_array().ok_or_else(|| ApiError::new(ErrorKind::InvalidInput, "payload is not an array").input("payload"))?;
+    let element = usize::try_from(nth).ok().and_then(|n| array.get(n))
+      .ok_or_else(|| ApiError::new(ErrorKind::InvalidInput, format!("{} is out of range for {} elements", nth, array.len())).input("nth"))?;
     Ok(OutputDataBuilder::new()
-      .data("json", Box::new(data[nth as usize].clone()))
+      .data("json", Box::new(element.clone()))
       .build())        
   }
 }
+impl Describe for Nth {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .input("payload", SocketType::Json)
+      .input("nth", SocketType::Number)
+      .control("nth", json!(0))
+      .output("json", SocketType::Json)
+  }
+}
 pub struct Combine;
 impl Worker for Combine {
   fn name(&self) -> &str {
@@ -292,14 +507,28 @@ impl Worker for Combine {
       .build())  
   }
 }
+impl Describe for Combine {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .optional("data1", SocketType::Any)
+      .optional("data2", SocketType::Any)
+      .optional("data3", SocketType::Any)
+      .optional("data4", SocketType::Any)
+      .control("name1", json!(""))
+      .control("name2", json!(""))
+      .control("name3", json!(""))
+      .control("name4", json!(""))
+      .output("json", SocketType::Json)
+  }
+}
 pub struct HandlebarsWorker;
 impl Worker for HandlebarsWorker {
   fn name(&self) -> &str {
       "Handlebars"
   }
   fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
-    let payload = node.get_json_field("payload", &input_data)?;
-    let template_txt = node.get_string_field("template", &input_data)?
+    let payload = node.get_json_field("payload", &input_data).map_err(invalid_input("payload"))?;
+    let template_txt = node.get_string_field("template", &input_data).map_err(invalid_input("template"))?
       .replace("\n", "");
     let reg = Handlebars::new();
     let output = reg.render_template(&template_txt, &json!({"payload": payload}))?;
@@ -311,6 +540,15 @@ impl Worker for HandlebarsWorker {
   
   }
 }
+impl Describe for HandlebarsWorker {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .input("payload", SocketType::Json)
+      .input("template", SocketType::Template)
+      .output("output", SocketType::String)
+      .output("json", SocketType::Json)
+  }
+}
 pub struct Input(pub Value);
 impl Worker for Input {
   fn name(&self) -> &str {
@@ -322,16 +560,26 @@ impl Worker for Input {
       .build())
   }
 }
+impl Describe for Input {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .output("payload", SocketType::Json)
+  }
+}
 pub struct Output;
 impl Worker for Output {
   fn name(&self) -> &str {
       "Output"
   }
   fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
-    let status = node.get_number_field("status", &input_data)?;
+    let status = node.get_number_field("status", &input_data).map_err(invalid_input("status"))?;
     let field = "payload";
     let payload = input_data.get(field)
-      .and_then(|i| i.get(&node.inputs.clone().map(|i| i[field].connections[0].output.clone()).unwrap_or_default()))
+      .and_then(|i| i.get(&node.inputs.as_ref()
+        .and_then(|i| i.get(field))
+        .and_then(|i| i.connections.first())
+        .map(|c| c.output.clone())
+        .unwrap_or_default()))
       .map(|data| -> Result<Value> { 
         data.get::<Value>().map(|r| r.clone()).ok_or(anyhow!("Unable to Get Value of Output "))
       }).ok_or(anyhow!("Unable to get the output value from inputs"))??;
@@ -341,6 +589,15 @@ impl Worker for Output {
       .build())        
   }
 }
+impl Describe for Output {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .action()
+      .input("payload", SocketType::Json)
+      .input("status", SocketType::Number)
+      .control("status", json!(200))
+  }
+}
 pub struct ScriptWorker;
 impl Worker for ScriptWorker {
   fn name(&self) -> &str {
@@ -349,8 +606,8 @@ impl Worker for ScriptWorker {
   fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
     let mut output_builder = OutputDataBuilder::new();
     match script_values(node, input_data) {
-      Ok((_src, payload, name)) => {
-        let src = format!("'use strict'; function main(payload) {{ {} }}", _src);
+      Ok((src, payload)) => {
+        let src = format!("'use strict'; function main(payload) {{ {} }}", src);
         match Script::from_string(&src) {
           Ok(mut script) => {
             let result: Result<Value, AnyError> = script.call("main", &payload);
@@ -358,22 +615,10 @@ impl Worker for ScriptWorker {
               Ok(payload) => {
                 output_builder.add_data("payload", Box::new(payload));
               },
-              Err(e) => {
-                let re = Regex::new(r"(?P<error>.+?)\n.+\(sandboxed.js:(?P<line>\d+):(?P<col>\d+)\)\n.+:\d+")?;
-                let er = format!("{:?}", &e);
-                let rep = format!("$error; @@ {} line: $line, col: $col", name);
-                let es = re.replace_all(&er, &rep).to_string();
-                bail!(es);
-              },
+              Err(e) => bail!(ApiError::script(&format!("{:?}", e))),
             };
           },
-          Err(e) => {
-            let re = Regex::new(r"(?P<error>.+?)\n.+sandboxed.js:(?P<line>\d+):(?P<col>\d+)")?;
-            let er = format!("{:?}", &e);
-            let rep = format!("$error; @@ {} line: $line, col: $col", name);
-            let es = re.replace_all(&er, &rep).to_string();
-            bail!(es);
-          }
+          Err(e) => bail!(ApiError::script(&format!("{:?}", e))),
         }
       },
       Err(e) => {
@@ -383,7 +628,117 @@ impl Worker for ScriptWorker {
     Ok(output_builder.build())
   }
 }
-pub struct MongodbGet(pub Rc<Client>);
+impl Describe for ScriptWorker {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .action()
+      .input("payload", SocketType::Json)
+      .control("name", json!(""))
+      .control("src", json!(""))
+      .output("payload", SocketType::Json)
+  }
+}
+pub struct Condition(pub FlowContext);
+impl Worker for Condition {
+  fn name(&self) -> &str {
+      "Condition"
+  }
+  fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
+    let (left, right, condition) = condition_values(node, input_data)?;
+    let result = condition::evaluate(&condition, &left, &right)?;
+    let (taken, untaken) = if result { ("true", "false") } else { ("false", "true") };
+    self.0.close_branches(node.id, &[taken], &[untaken]);
+    Ok(OutputDataBuilder::new()
+      .data("true", Box::new(result))
+      .data("false", Box::new(!result))
+      .build())
+  }
+}
+impl Describe for Condition {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .action()
+      .optional("left", SocketType::Any)
+      .optional("right", SocketType::Any)
+      .control("condition", json!("=="))
+      .output("true", SocketType::Action)
+      .output("false", SocketType::Action)
+  }
+}
+/// Routes to the output of the first case whose value equals the input, or to
+/// `default`. Cases are `{"name": ..., "value": ...}` entries in the node data,
+/// a case without a value matches its own name.
+pub struct Switch(pub FlowContext);
+impl Worker for Switch {
+  fn name(&self) -> &str {
+      "Switch"
+  }
+  fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
+    let value = node.get_as_json_field_or("value", &input_data, Some(Value::Null)).map_err(invalid_input("value"))?;
+    // `field` picks a dotted path out of the value, like `type` of a payload
+    let value = match node.data.as_ref().and_then(|d| d["field"].as_str()).and_then(fix_empty_str) {
+      Some(path) => value.pointer(&format!("/{}", path.replace('.', "/"))).cloned().unwrap_or(Value::Null),
+      None => value,
+    };
+    let cases = switch_cases(node);
+    let taken = cases.iter().find(|(_, v)| *v == value).map(|(name, _)| name.as_str()).unwrap_or("default");
+    let untaken: Vec<&str> = cases.iter().map(|(name, _)| name.as_str())
+      .chain(std::iter::once("default"))
+      .filter(|name| *name != taken)
+      .collect();
+    self.0.close_branches(node.id, &[taken], &untaken);
+    let mut output_builder = OutputDataBuilder::new();
+    output_builder.add_data(taken, Box::new(true));
+    for name in &untaken {
+      output_builder.add_data(name, Box::new(false));
+    }
+    Ok(output_builder.build())
+  }
+}
+impl Describe for Switch {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .action()
+      .optional("value", SocketType::Any)
+      .control("field", json!(""))
+      .dynamic_outputs("cases", SocketType::Action)
+      .output("default", SocketType::Action)
+  }
+}
+/// Guards the nodes behind its `try` output. When one of them fails the rest of
+/// that branch is skipped and the nodes behind `error` run instead, with the
+/// structured error (node, kind, message) on the `error` output. The payload is
+/// passed on to both branches.
+pub struct Try(pub FlowContext);
+impl Worker for Try {
+  fn name(&self) -> &str {
+      "Try"
+  }
+  fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
+    let payload = node.get_as_json_field_or("payload", &input_data, Some(Value::Null)).map_err(invalid_input("payload"))?;
+    let caught = self.0.caught(node.id);
+    match &caught {
+      Some(_) => self.0.close_branches(node.id, &["error"], &["try"]),
+      None => self.0.close_branches(node.id, &["try"], &["error"]),
+    }
+    Ok(OutputDataBuilder::new()
+      .data("try", Box::new(caught.is_none()))
+      .data("error", Box::new(caught.map(serde_json::to_value).transpose()?.unwrap_or(Value::Null)))
+      .data("payload", Box::new(payload))
+      .build())
+  }
+}
+impl Describe for Try {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .action()
+      .optional("payload", SocketType::Json)
+      .output("try", SocketType::Action)
+      .output("error", SocketType::Json)
+      .output("payload", SocketType::Json)
+  }
+}
+pub struct MongodbGet(pub Rc<Client>, pub Rc<MongoPolicy>, pub Tenant);
 impl Worker for MongodbGet {
   fn name(&self) -> &str {
       "MongoDB Get"
@@ -391,20 +746,30 @@ impl Worker for MongodbGet {
   fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
     let dbname = node.get_string_field("dbname", &input_data).unwrap_or("flows".to_string());
     let colname = node.get_string_field("colname", &input_data).unwrap_or("cache".to_string());
-    let squery = node.get_string_field("query", &input_data)?;
+    let squery = node.get_string_field("query", &input_data).map_err(invalid_input("query"))?;
     let limit = node.get_number_field("limit", &input_data).unwrap_or(10);
+    let skip = node.get_number_field("skip", &input_data).unwrap_or(0);
+    let sort = node.get_as_json_field("sort", &input_data).ok().map(|s| sort_document(&s)).transpose()?.flatten();
+    let projection = node.get_json_field("projection", &input_data).ok()
+      .filter(|p| p.as_object().map(|o| !o.is_empty()).unwrap_or(false))
+      .map(|p| to_bson_owned(&p)).transpose()?;
+
+    self.1.check(self.name(), Access::Read, &dbname, &colname)?;
 
     let db = self.0.database(&dbname);
     let coll = db.collection(&colname);
     let pquery = query::parse::from_str(&squery);
-    let query = if dbname == "flows" {
+    let query = self.2.filter(if dbname == "flows" {
       mongo::to_bson(pquery)
     } else {
       mongo::to_bson(query!(..pquery && "deleted" == false))
-    };
+    });
 
     let options = FindOptions::builder()
       .limit(limit)
+      .skip(skip)
+      .sort(sort)
+      .projection(projection)
       .build();
     let mut output_builder = OutputDataBuilder::new();
     match coll.find(query.clone(), Some(options)) {
@@ -417,23 +782,49 @@ impl Worker for MongodbGet {
         bail!(e);
       }
     };
+    if output_connected(node, "count") {
+      let count = coll.count_documents(query, None)?;
+      output_builder.add_data("count", Box::new(count));
+    }
     Ok(output_builder.build())
   }
 }
-pub struct MongodbInsert(pub Rc<Client>);
+impl Describe for MongodbGet {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .action()
+      .optional("dbname", SocketType::String)
+      .optional("colname", SocketType::String)
+      .input("query", SocketType::String)
+      .optional("limit", SocketType::Number)
+      .optional("skip", SocketType::Number)
+      .optional("sort", SocketType::Any)
+      .optional("projection", SocketType::Json)
+      .control("dbname", json!("flows"))
+      .control("colname", json!("cache"))
+      .control("query", json!("deleted == false"))
+      .control("limit", json!(20))
+      .output("json", SocketType::Json)
+      .output("count", SocketType::Number)
+  }
+}
+pub struct MongodbInsert(pub Rc<Client>, pub Rc<MongoPolicy>, pub Tenant);
 impl Worker for MongodbInsert {
   fn name(&self) -> &str {
       "MongoDB Insert"
   }
   fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
-    let dbname = "flows".to_string();
-    let colname = node.get_string_field("colname", &input_data)?;
-    let mut payload = node.get_json_field("payload", &input_data)?;
+    let dbname = node.get_string_field("dbname", &input_data).ok().and_then(fix_empty_string).unwrap_or("flows".to_string());
+    let colname = node.get_string_field("colname", &input_data).map_err(invalid_input("colname"))?;
+    let mut payload = node.get_json_field("payload", &input_data).map_err(invalid_input("payload"))?;
+
+    self.1.check(self.name(), Access::Write, &dbname, &colname)?;
 
     let db = self.0.database(&dbname);
     let coll = db.collection(&colname);
     let mut builder = OutputDataBuilder::new();
-    let data = to_bson_owned(&payload)?;
+    let mut data = to_bson_owned(&payload)?;
+    self.2.stamp(&mut data);
     match coll.insert_one(data, None) {
       Ok(res) => {
         let rr: Value = bson::from_bson(bson::to_bson(&res).unwrap())?;
@@ -447,23 +838,38 @@ impl Worker for MongodbInsert {
     Ok(builder.build())
   }
 }
-pub struct MongodbReplace(pub Rc<Client>);
+impl Describe for MongodbInsert {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .action()
+      .optional("dbname", SocketType::String)
+      .input("colname", SocketType::String)
+      .input("payload", SocketType::Json)
+      .control("dbname", json!("flows"))
+      .control("colname", json!("cache"))
+      .output("json", SocketType::Json)
+  }
+}
+pub struct MongodbReplace(pub Rc<Client>, pub Rc<MongoPolicy>, pub Tenant);
 impl Worker for MongodbReplace {
   fn name(&self) -> &str {
       "MongoDB Replace"
   }
   fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
-    let dbname = "flows".to_string();
-    let colname = node.get_string_field("colname", &input_data)?;
-    let payload = node.get_json_field("payload", &input_data)?;
-    let squery = node.get_string_field("query", &input_data)?;
+    let dbname = node.get_string_field("dbname", &input_data).ok().and_then(fix_empty_string).unwrap_or("flows".to_string());
+    let colname = node.get_string_field("colname", &input_data).map_err(invalid_input("colname"))?;
+    let payload = node.get_json_field("payload", &input_data).map_err(invalid_input("payload"))?;
+    let squery = node.get_string_field("query", &input_data).map_err(invalid_input("query"))?;
+
+    let query = self.2.filter(mongo::to_bson(query::parse::from_str(&squery)));
 
-    let query = mongo::to_bson(query::parse::from_str(&squery));
+    self.1.check(self.name(), Access::Write, &dbname, &colname)?;
 
     let db = self.0.database(&dbname);
     let coll = db.collection(&colname);
 
-    let data = to_bson_owned(&payload)?;
+    let mut data = to_bson_owned(&payload)?;
+    self.2.stamp(&mut data);
     let mut builder = OutputDataBuilder::new();
     match coll.find_one_and_replace(query, data, None) {
       Ok(Some(res)) => {
@@ -480,34 +886,236 @@ impl Worker for MongodbReplace {
     Ok(builder.build())
   }
 }
+impl Describe for MongodbReplace {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .action()
+      .optional("dbname", SocketType::String)
+      .input("colname", SocketType::String)
+      .input("query", SocketType::String)
+      .input("payload", SocketType::Json)
+      .control("dbname", json!("flows"))
+      .control("colname", json!("cache"))
+      .control("query", json!("deleted == false"))
+      .output("json", SocketType::Json)
+  }
+}
+
+pub struct MongodbUpdate(pub Rc<Client>, pub Rc<MongoPolicy>, pub Tenant);
+impl Worker for MongodbUpdate {
+  fn name(&self) -> &str {
+      "MongoDB Update"
+  }
+  fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
+    let dbname = node.get_string_field("dbname", &input_data).ok().and_then(fix_empty_string).unwrap_or("flows".to_string());
+    let colname = node.get_string_field("colname", &input_data).map_err(invalid_input("colname"))?;
+    let payload = node.get_json_field("payload", &input_data).map_err(invalid_input("payload"))?;
+    let squery = node.get_string_field("query", &input_data).map_err(invalid_input("query"))?;
+    let many = node_flag(node, "many");
+    let upsert = node_flag(node, "upsert");
+
+    let pquery = query::parse::from_str(&squery);
+    let query = self.2.filter(mongo::to_bson(query!(..pquery && "deleted" == false)));
+    let update = update_document(&payload)?;
+
+    self.1.check(self.name(), Access::Write, &dbname, &colname)?;
+    self.2.check_update(self.name(), &update)?;
+
+    let db = self.0.database(&dbname);
+    let coll = db.collection(&colname);
+
+    let (result, matched, modified) = if many {
+      let id_options = FindOptions::builder().projection(doc!{"_id": 1}).build();
+      let mut ids: Vec<Bson> = coll.find(query.clone(), Some(id_options))?
+        .filter_map(|d| d.ok().and_then(|d| d.get("_id").cloned()))
+        .collect();
+      let (matched, modified) = if ids.is_empty() && upsert {
+        let options = UpdateOptions::builder().upsert(true).build();
+        let res = coll.update_one(query, update, Some(options))?;
+        ids.extend(res.upserted_id);
+        (res.matched_count, res.modified_count)
+      } else if ids.is_empty() {
+        (0, 0)
+      } else {
+        // documents that stopped matching since they were listed are left alone
+        let res = coll.update_many(doc!{"$and": [query, {"_id": {"$in": ids.clone()}}]}, update, None)?;
+        (res.matched_count, res.modified_count)
+      };
+      let cursor = coll.find(doc!{"_id": {"$in": ids}}, None)?;
+      let docs: Vec<Value> = to_vec!(cursor);
+      (json!(docs), matched, modified)
+    } else {
+      let options = FindOneAndUpdateOptions::builder()
+        .upsert(upsert)
+        .return_document(ReturnDocument::Before)
+        .build();
+      let (after, matched, modified) = match coll.find_one_and_update(query.clone(), update, Some(options))? {
+        Some(before) => {
+          let after = coll.find_one(doc!{"_id": before.get("_id").cloned().unwrap_or(Bson::Null)}, None)?;
+          let modified = after.as_ref() != Some(&before);
+          (after, 1, modified as i64)
+        },
+        // the upserted document is read back through the filter
+        None if upsert => (coll.find_one(query, None)?, 0, 0),
+        None => (None, 0, 0),
+      };
+      let result = match after {
+        Some(doc) => bson::from_bson(Bson::Document(doc))?,
+        None => Value::Null,
+      };
+      (result, matched, modified)
+    };
+
+    Ok(OutputDataBuilder::new()
+      .data("json", Box::new(result))
+      .data("matched", Box::new(matched))
+      .data("modified", Box::new(modified))
+      .build())
+  }
+}
+impl Describe for MongodbUpdate {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .action()
+      .optional("dbname", SocketType::String)
+      .input("colname", SocketType::String)
+      .input("query", SocketType::String)
+      .input("payload", SocketType::Json)
+      .control("dbname", json!("flows"))
+      .control("colname", json!("cache"))
+      .control("query", json!("deleted == false"))
+      .control("many", json!(false))
+      .control("upsert", json!(false))
+      .output("json", SocketType::Json)
+      .output("matched", SocketType::Number)
+      .output("modified", SocketType::Number)
+  }
+}
+
+pub struct MongodbDelete(pub Rc<Client>, pub Rc<MongoPolicy>, pub Tenant);
+impl Worker for MongodbDelete {
+  fn name(&self) -> &str {
+      "MongoDB Delete"
+  }
+  fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
+    let dbname = node.get_string_field("dbname", &input_data).ok().and_then(fix_empty_string).unwrap_or("flows".to_string());
+    let colname = node.get_string_field("colname", &input_data).map_err(invalid_input("colname"))?;
+    let squery = node.get_string_field("query", &input_data).map_err(invalid_input("query"))?;
+    let many = node_flag(node, "many");
+    let hard = node_flag(node, "hard");
+
+    self.1.check(self.name(), Access::Write, &dbname, &colname)?;
+
+    let db = self.0.database(&dbname);
+    let coll = db.collection(&colname);
+    let pquery = query::parse::from_str(&squery);
+
+    let count = if hard {
+      let query = self.2.filter(mongo::to_bson(pquery));
+      let res = if many { coll.delete_many(query, None)? } else { coll.delete_one(query, None)? };
+      res.deleted_count
+    } else {
+      let query = self.2.filter(mongo::to_bson(query!(..pquery && "deleted" == false)));
+      let update = doc!{"$set": {"deleted": true, "deleted_at": current_millis()?}};
+      let res = if many { coll.update_many(query, update, None)? } else { coll.update_one(query, update, None)? };
+      res.modified_count
+    };
+    Ok(OutputDataBuilder::new()
+      .data("count", Box::new(count as i64))
+      .build())
+  }
+}
+impl Describe for MongodbDelete {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .action()
+      .optional("dbname", SocketType::String)
+      .input("colname", SocketType::String)
+      .input("query", SocketType::String)
+      .control("dbname", json!("flows"))
+      .control("colname", json!("cache"))
+      .control("query", json!("deleted == false"))
+      .control("many", json!(false))
+      .control("hard", json!(false))
+      .output("count", SocketType::Number)
+  }
+}
+
+pub struct MongodbAggregate(pub Rc<Client>, pub Rc<MongoPolicy>, pub Tenant);
+impl Worker for MongodbAggregate {
+  fn name(&self) -> &str {
+      "MongoDB Aggregate"
+  }
+  fn work(&self, node: &Node, input_data: InputData) -> Result<OutputData> {
+    let dbname = node.get_string_field("dbname", &input_data).unwrap_or("flows".to_string());
+    let colname = node.get_string_field("colname", &input_data).unwrap_or("cache".to_string());
+    let stages = match node.get_as_json_field("pipeline", &input_data).map_err(invalid_input("pipeline"))? {
+      Value::String(s) => serde_json::from_str::<Value>(&s)?,
+      v => v,
+    };
+    let stages = match stages {
+      Value::Array(arr) => arr,
+      _ => bail!("pipeline must be an array of stages"),
+    };
+
+    self.1.check(self.name(), Access::Read, &dbname, &colname)?;
+    self.1.check_pipeline(self.name(), &dbname, &stages)?;
+
+    let mut pipeline: Vec<bson::Document> = stages.iter().map(to_bson_owned).collect::<Result<Vec<_>>>()?;
+    if dbname != "flows" {
+      pipeline.insert(0, doc!{"$match": {"deleted": false}});
+    }
+    let pipeline = self.2.pipeline(self.name(), pipeline)?;
+
+    let db = self.0.database(&dbname);
+    let coll = db.collection(&colname);
+    let cursor = coll.aggregate(pipeline, None)?;
+    let vec: Vec<Value> = to_vec!(cursor);
+    Ok(OutputDataBuilder::new()
+      .data("json", Box::new(json!(vec)))
+      .build())
+  }
+}
+impl Describe for MongodbAggregate {
+  fn describe(&self) -> NodeSpec {
+    NodeSpec::new(self.name())
+      .action()
+      .optional("dbname", SocketType::String)
+      .optional("colname", SocketType::String)
+      .input("pipeline", SocketType::Json)
+      .control("dbname", json!("flows"))
+      .control("colname", json!("cache"))
+      .output("json", SocketType::Json)
+  }
+}
 
 fn array_map_values(node: &Node, input_data: InputData) -> Result<(Value, String), anyhow::Error> {
   Ok((
-    node.get_json_field("payload", &input_data)?,
-    node.get_string_field("fields", &input_data)?
+    node.get_json_field("payload", &input_data).map_err(invalid_input("payload"))?,
+    node.get_string_field("fields", &input_data).map_err(invalid_input("fields"))?
   ))
 }
 
 fn array_sum_values(node: &Node, input_data: InputData) -> Result<(Value, String), anyhow::Error> {
   Ok((
-    node.get_json_field("payload", &input_data)?,
-    node.get_string_field("field", &input_data)?
+    node.get_json_field("payload", &input_data).map_err(invalid_input("payload"))?,
+    node.get_string_field("field", &input_data).map_err(invalid_input("field"))?
   ))
 }
 
 fn nth_values(node: &Node, input_data: InputData) -> Result<(Value, i64), anyhow::Error> {
   Ok((
-    node.get_json_field("payload", &input_data)?,
-    node.get_number_field("nth", &input_data)?
+    node.get_json_field("payload", &input_data).map_err(invalid_input("payload"))?,
+    node.get_number_field("nth", &input_data).map_err(invalid_input("nth"))?
   ))
 }
 
 fn combine_values(node: &Node, input_data: InputData) -> Result<(Value, Value, Value, Value, String, String, String, String), anyhow::Error> {
   Ok((
-    node.get_as_json_field_or("data1", &input_data, Some(json!({})))?,
-    node.get_as_json_field_or("data2", &input_data, Some(json!({})))?,
-    node.get_as_json_field_or("data3", &input_data, Some(json!({})))?,
-    node.get_as_json_field_or("data4", &input_data, Some(json!({})))?,
+    node.get_as_json_field_or("data1", &input_data, Some(json!({}))).map_err(invalid_input("data1"))?,
+    node.get_as_json_field_or("data2", &input_data, Some(json!({}))).map_err(invalid_input("data2"))?,
+    node.get_as_json_field_or("data3", &input_data, Some(json!({}))).map_err(invalid_input("data3"))?,
+    node.get_as_json_field_or("data4", &input_data, Some(json!({}))).map_err(invalid_input("data4"))?,
     node.get_string_field("name1", &input_data).ok().and_then(fix_empty_string).unwrap_or("data1".to_string()),
     node.get_string_field("name2", &input_data).ok().and_then(fix_empty_string).unwrap_or("data2".to_string()),
     node.get_string_field("name3", &input_data).ok().and_then(fix_empty_string).unwrap_or("data3".to_string()),
@@ -515,14 +1123,96 @@ fn combine_values(node: &Node, input_data: InputData) -> Result<(Value, Value, V
   ))
 }
 
-fn script_values(node: &Node, input_data: InputData) -> Result<(String, Value, String), anyhow::Error> {
+fn script_values(node: &Node, input_data: InputData) -> Result<(String, Value), anyhow::Error> {
+  Ok((
+    node.get_string_field("src", &input_data).map_err(invalid_input("src"))?,
+    node.get_json_field("payload", &input_data).map_err(invalid_input("payload"))?,
+  ))
+}
+
+fn switch_cases(node: &Node) -> Vec<(String, Value)> {
+  node.data.as_ref().and_then(|d| d["cases"].as_array()).into_iter().flatten()
+    .filter_map(|case| case["name"].as_str().and_then(fix_empty_str).filter(|name| *name != "default").map(|name| {
+      let value = match &case["value"] {
+        Value::Null => Value::String(name.to_string()),
+        value => value.clone(),
+      };
+      (name.to_string(), value)
+    }))
+    .collect()
+}
+
+fn condition_values(node: &Node, input_data: InputData) -> Result<(Value, Value, String), anyhow::Error> {
   Ok((
-    node.get_string_field("src", &input_data)?,
-    node.get_json_field("payload", &input_data)?,
-    node.get_string_field("name", &input_data)?
+    node.get_as_json_field_or("left", &input_data, Some(Value::Null)).map_err(invalid_input("left"))?,
+    node.get_as_json_field_or("right", &input_data, Some(Value::Null)).map_err(invalid_input("right"))?,
+    node.data.as_ref().and_then(|d| d["condition"].as_str()).unwrap_or("==").to_string()
   ))
 }
 
+/// Accepts either a sort document (`{"name": 1, "created": "desc"}`) or a comma
+/// separated list of fields where a leading `-` sorts descending (`name,-created`).
+fn sort_document(sort: &Value) -> Result<Option<bson::Document>> {
+  match sort {
+    Value::Null => Ok(None),
+    Value::String(s) if s.trim().is_empty() => Ok(None),
+    Value::String(s) => {
+      let mut doc = bson::Document::new();
+      for field in s.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+        match field.strip_prefix('-') {
+          Some(f) => doc.insert(f, -1),
+          None => doc.insert(field.trim_start_matches('+'), 1),
+        };
+      }
+      Ok(Some(doc))
+    },
+    Value::Object(o) if o.is_empty() => Ok(None),
+    Value::Object(o) => {
+      let mut doc = bson::Document::new();
+      for (field, order) in o {
+        match order {
+          Value::String(s) if s.eq_ignore_ascii_case("asc") => doc.insert(field, 1),
+          Value::String(s) if s.eq_ignore_ascii_case("desc") => doc.insert(field, -1),
+          Value::Number(n) if n.as_i64() == Some(1) => doc.insert(field, 1),
+          Value::Number(n) if n.as_i64() == Some(-1) => doc.insert(field, -1),
+          _ => bail!("sort order of {} must be 1, -1, \"asc\" or \"desc\"", field),
+        };
+      }
+      Ok(Some(doc))
+    },
+    _ => bail!("sort must be a document or a list of fields"),
+  }
+}
+
+fn node_flag(node: &Node, field: &str) -> bool {
+  node.data.as_ref().and_then(|d| d[field].as_bool()).unwrap_or(false)
+}
+
+/// Whether anything reads the output `key` of `node`
+fn output_connected(node: &Node, key: &str) -> bool {
+  node.outputs.as_ref().and_then(|o| o.get(key)).map(|o| !o.connections.is_empty()).unwrap_or(false)
+}
+
+/// Uses the payload as is when it holds update operators (`$set`, `$inc`, ...),
+/// otherwise treats it as the fields to `$set`.
+fn update_document(payload: &Value) -> Result<bson::Document> {
+  let data = to_bson_owned(payload)?;
+  if data.is_empty() {
+    bail!("update payload is empty");
+  }
+  let update = if data.keys().all(|k| k.starts_with('$')) {
+    data
+  } else if data.keys().any(|k| k.starts_with('$')) {
+    bail!("update payload mixes operators and fields")
+  } else {
+    doc!{"$set": data}
+  };
+  if update.get_document("$set").map(|set| set.contains_key("_id")).unwrap_or(false) {
+    bail!("update payload can't set _id");
+  }
+  Ok(update)
+}
+
 fn to_bson_owned<A>(a: &A) -> Result<bson::Document> where A: serde::Serialize {
   let b = bson::to_bson(a)?;
   let doc = b.as_document().ok_or(anyhow!("Unable to make document owned"))?;
@@ -532,6 +1222,7 @@ fn to_bson_owned<A>(a: &A) -> Result<bson::Document> where A: serde::Serialize {
 #[cfg(test)]
 mod node_test {
   use crate::nocode::nodes;
+  use crate::nocode::flow::FlowContext;
   use d3ne::*;
   use serde_json::Value;
 
@@ -1014,6 +1705,267 @@ mod node_test {
     assert_eq!(result, &json!({"query": r#"_id in ['1','2','3','4','5']"#}));
   }
 
+  #[test]
+  fn branches() {
+    let json_data = json!(
+      {
+        "id": "tests@1.0.0",
+        "nodes": {
+          "1": {
+            "id": 1,
+            "data": { "num": 25 },
+            "inputs": {},
+            "outputs": {
+              "num": { "connections": [{ "node": 2, "input": "left", "data": {} }] }
+            },
+            "position": [0, 0],
+            "name": "Number"
+          },
+          "2": {
+            "id": 2,
+            "data": { "condition": ">", "right": 18 },
+            "inputs": {
+              "left": { "connections": [{ "node": 1, "output": "num", "data": {} }] }
+            },
+            "outputs": {
+              "true": { "connections": [{ "node": 4, "input": "action", "data": {} }] },
+              "false": { "connections": [{ "node": 5, "input": "action", "data": {} }] }
+            },
+            "position": [0, 0],
+            "name": "Condition"
+          },
+          "3": {
+            "id": 3,
+            "data": { "json": { "adult": true } },
+            "inputs": {},
+            "outputs": {
+              "json": { "connections": [{ "node": 4, "input": "payload", "data": {} }, { "node": 5, "input": "payload", "data": {} }] }
+            },
+            "position": [0, 0],
+            "name": "Json"
+          },
+          "4": {
+            "id": 4,
+            "data": { "status": 200 },
+            "inputs": {
+              "action": { "connections": [{ "node": 2, "output": "true", "data": {} }] },
+              "payload": { "connections": [{ "node": 3, "output": "json", "data": {} }] }
+            },
+            "outputs": {},
+            "position": [0, 0],
+            "name": "Output"
+          },
+          "5": {
+            "id": 5,
+            "data": { "status": 400 },
+            "inputs": {
+              "action": { "connections": [{ "node": 2, "output": "false", "data": {} }] },
+              "payload": { "connections": [{ "node": 3, "output": "json", "data": {} }] }
+            },
+            "outputs": {},
+            "position": [0, 0],
+            "name": "Output"
+          }
+        }
+      }
+    );
+    let ctx = FlowContext::new();
+    let mut workers = WorkersBuilder::new();
+    workers.add(ctx.guard(nodes::Number))
+      .add(ctx.guard(nodes::JsonData))
+      .add(ctx.guard(nodes::Condition(ctx.clone())))
+      .add(ctx.guard(nodes::Output));
+
+    let engine = Engine::new("tests@1.0.0", workers.build());
+    let nodes = engine.parse_value(json_data).unwrap();
+    ctx.load(&nodes);
+    engine.process(&nodes, 1).unwrap();
+    let oo = ctx.output().unwrap();
+    let status = oo["status"].get::<i64>().unwrap();
+    assert_eq!(status, &200i64);
+  }
+
+  #[test]
+  fn switch_branches() {
+    let output = |id: i64, case: &str, status: i64| json!({
+      "id": id,
+      "data": { "status": status },
+      "inputs": {
+        "action": { "connections": [{ "node": 2, "output": case, "data": {} }] },
+        "payload": { "connections": [{ "node": 1, "output": "payload", "data": {} }] }
+      },
+      "outputs": {},
+      "position": [0, 0],
+      "name": "Output"
+    });
+    let json_data = json!(
+      {
+        "id": "tests@1.0.0",
+        "nodes": {
+          "1": {
+            "id": 1,
+            "data": {},
+            "inputs": {},
+            "outputs": {
+              "payload": { "connections": [
+                { "node": 2, "input": "value", "data": {} },
+                { "node": 3, "input": "payload", "data": {} },
+                { "node": 4, "input": "payload", "data": {} },
+                { "node": 5, "input": "payload", "data": {} }
+              ] }
+            },
+            "position": [0, 0],
+            "name": "Input"
+          },
+          "2": {
+            "id": 2,
+            "data": { "field": "type", "cases": [{ "name": "order" }, { "name": "refund", "value": "refund" }] },
+            "inputs": {
+              "value": { "connections": [{ "node": 1, "output": "payload", "data": {} }] }
+            },
+            "outputs": {
+              "order": { "connections": [{ "node": 3, "input": "action", "data": {} }] },
+              "refund": { "connections": [{ "node": 4, "input": "action", "data": {} }] },
+              "default": { "connections": [{ "node": 5, "input": "action", "data": {} }] }
+            },
+            "position": [0, 0],
+            "name": "Switch"
+          },
+          "3": output(3, "order", 200),
+          "4": output(4, "refund", 201),
+          "5": output(5, "default", 404)
+        }
+      }
+    );
+    for (kind, expected) in &[("order", 200i64), ("refund", 201), ("other", 404)] {
+      let ctx = FlowContext::new();
+      let mut workers = WorkersBuilder::new();
+      workers.add(ctx.guard(nodes::Input(json!({ "type": kind }))))
+        .add(ctx.guard(nodes::Switch(ctx.clone())))
+        .add(ctx.guard(nodes::Output));
+
+      let engine = Engine::new("tests@1.0.0", workers.build());
+      let nodes = engine.parse_value(json_data.clone()).unwrap();
+      ctx.load(&nodes);
+      engine.process(&nodes, 1).unwrap();
+      let oo = ctx.output().unwrap();
+      let status = oo["status"].get::<i64>().unwrap();
+      assert_eq!(status, expected);
+    }
+  }
+  #[test]
+  fn try_catches_branch_errors() {
+    let json_data = json!(
+      {
+        "id": "tests@1.0.0",
+        "nodes": {
+          "1": {
+            "id": 1,
+            "data": {},
+            "inputs": {},
+            "outputs": {
+              "payload": { "connections": [{ "node": 2, "input": "payload", "data": {} }] }
+            },
+            "position": [0, 0],
+            "name": "Input"
+          },
+          "2": {
+            "id": 2,
+            "data": {},
+            "inputs": {
+              "payload": { "connections": [{ "node": 1, "output": "payload", "data": {} }] }
+            },
+            "outputs": {
+              "try": { "connections": [{ "node": 3, "input": "action", "data": {} }] },
+              "payload": { "connections": [{ "node": 3, "input": "payload", "data": {} }] },
+              "error": { "connections": [{ "node": 5, "input": "payload", "data": {} }] }
+            },
+            "position": [0, 0],
+            "name": "Try"
+          },
+          "3": {
+            "id": 3,
+            "data": { "name": "fails", "src": "throw new Error('boom');" },
+            "inputs": {
+              "action": { "connections": [{ "node": 2, "output": "try", "data": {} }] },
+              "payload": { "connections": [{ "node": 2, "output": "payload", "data": {} }] }
+            },
+            "outputs": {
+              "payload": { "connections": [{ "node": 4, "input": "payload", "data": {} }] }
+            },
+            "position": [0, 0],
+            "name": "Script"
+          },
+          "4": {
+            "id": 4,
+            "data": { "status": 200 },
+            "inputs": {
+              "payload": { "connections": [{ "node": 3, "output": "payload", "data": {} }] }
+            },
+            "outputs": {},
+            "position": [0, 0],
+            "name": "Output"
+          },
+          "5": {
+            "id": 5,
+            "data": { "status": 502 },
+            "inputs": {
+              "payload": { "connections": [{ "node": 2, "output": "error", "data": {} }] }
+            },
+            "outputs": {},
+            "position": [0, 0],
+            "name": "Output"
+          }
+        }
+      }
+    );
+    let ctx = FlowContext::new();
+    // an engine is spent by its run, so every pass gets its own
+    let engine = || {
+      let mut workers = WorkersBuilder::new();
+      workers.add(ctx.guard(nodes::Input(json!({"a": 1}))))
+        .add(ctx.guard(nodes::Try(ctx.clone())))
+        .add(ctx.guard(nodes::ScriptWorker))
+        .add(ctx.guard(nodes::Output));
+      Engine::new("tests@1.0.0", workers.build())
+    };
+
+    let nodes = engine().parse_value(json_data).unwrap();
+    ctx.load(&nodes);
+    engine().process(&nodes, 1).unwrap();
+    assert!(ctx.next_pass());
+    engine().process(&nodes, 1).unwrap();
+    assert!(!ctx.next_pass());
+    let oo = ctx.output().unwrap();
+    assert_eq!(oo["status"].get::<i64>().unwrap(), &502i64);
+    let error = oo["payload"].get::<Value>().unwrap();
+    assert_eq!(error["node"], json!(3));
+    assert_eq!(error["kind"], json!("script"));
+  }
+
+
+  #[test]
+  fn builds_sort_documents() {
+    assert_eq!(nodes::sort_document(&json!({"name": 1, "created": -1})).unwrap(), Some(doc!{"name": 1, "created": -1}));
+    assert_eq!(nodes::sort_document(&json!("name,-created")).unwrap(), Some(doc!{"name": 1, "created": -1}));
+    assert_eq!(nodes::sort_document(&json!(" ")).unwrap(), None);
+    assert_eq!(nodes::sort_document(&json!({})).unwrap(), None);
+    assert_eq!(nodes::sort_document(&json!({"name": "asc", "created": "desc"})).unwrap(), Some(doc!{"name": 1, "created": -1}));
+    assert!(nodes::sort_document(&json!({"name": 2})).is_err());
+    assert!(nodes::sort_document(&json!({"name": "up"})).is_err());
+    assert!(nodes::sort_document(&json!(1)).is_err());
+  }
+
+  #[test]
+  fn builds_update_documents() {
+    assert_eq!(nodes::update_document(&json!({"$inc": {"n": 1}})).unwrap(), doc!{"$inc": {"n": 1}});
+    assert_eq!(nodes::update_document(&json!({"name": "a"})).unwrap(), doc!{"$set": {"name": "a"}});
+    assert!(nodes::update_document(&json!({"$set": {"name": "a"}, "n": 1})).is_err());
+    assert!(nodes::update_document(&json!({})).is_err());
+    assert!(nodes::update_document(&json!({"_id": 1, "name": "a"})).is_err());
+    assert!(nodes::update_document(&json!({"$set": {"_id": 1}})).is_err());
+  }
+
   #[test]
   fn realtest() {
     let json_data = r#"
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::env;
+use serde_json::Value;
+use bson::{Bson, Document};
+use anyhow::Result;
+
+use crate::nocode::error::{ApiError, ErrorKind};
+use crate::util::bson_or_null;
+
+/// Collections that hold the flow and rule definitions and api key revocations.
+/// A wildcard entry never grants access to them, they have to be listed by name.
+const RESERVED: [&str; 5] = ["flows", "flowsmeta", "rules", "rulesmeta", "revocations"];
+/// Aggregation stages that reach past the tenant's documents
+const TENANT_FORBIDDEN: [&str; 6] = ["$lookup", "$graphLookup", "$unionWith", "$facet", "$out", "$merge"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+  Read,
+  Write,
+}
+
+impl std::fmt::Display for Access {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", match self {
+      Access::Read => "read",
+      Access::Write => "write",
+    })
+  }
+}
+
+/// Which databases and collections the Mongo nodes may touch, keyed by
+/// database name with a list of collections (`*` for any collection).
+/// Loaded from `MONGO_POLICY`, e.g. `{"read": {"flows": ["*"]}, "write": {"flows": ["cache"]}}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct MongoPolicy {
+  #[serde(default)]
+  pub read: HashMap<String, Vec<String>>,
+  #[serde(default)]
+  pub write: HashMap<String, Vec<String>>,
+}
+
+impl Default for MongoPolicy {
+  fn default() -> Self {
+    let all: HashMap<String, Vec<String>> = vec![("flows".to_string(), vec!["*".to_string()])].into_iter().collect();
+    MongoPolicy { read: all.clone(), write: all }
+  }
+}
+
+impl MongoPolicy {
+  pub fn from_env() -> Result<Self, serde_json::Error> {
+    match env::var("MONGO_POLICY") {
+      Ok(policy) => serde_json::from_str(&policy),
+      Err(_) => Ok(MongoPolicy::default()),
+    }
+  }
+
+  pub fn allows(&self, access: Access, dbname: &str, colname: &str) -> bool {
+    let rules = match access {
+      Access::Read => &self.read,
+      Access::Write => &self.write,
+    };
+    rules.get(dbname).map(|cols| cols.iter().any(|c| {
+      c == colname || (c == "*" && !RESERVED.contains(&colname))
+    })).unwrap_or(false)
+  }
+
+  pub fn check(&self, node: &str, access: Access, dbname: &str, colname: &str) -> Result<()> {
+    if self.allows(access, dbname, colname) {
+      Ok(())
+    } else {
+      Err(ApiError::new(ErrorKind::Forbidden, format!("{} is not allowed to {} {}.{}", node, access, dbname, colname)).into())
+    }
+  }
+
+  /// Checks the collections an aggregation pipeline pulls in or writes to,
+  /// sub-pipelines included
+  pub fn check_pipeline(&self, node: &str, dbname: &str, stages: &[Value]) -> Result<()> {
+    for (op, arg) in all_stages(stages) {
+      match op {
+        "$lookup" | "$graphLookup" => if let Some(from) = arg["from"].as_str() {
+          self.check(node, Access::Read, dbname, from)?;
+        },
+        "$unionWith" => if let Some(coll) = arg.as_str().or(arg["coll"].as_str()) {
+          self.check(node, Access::Read, dbname, coll)?;
+        },
+        "$out" | "$merge" => match arg {
+          Value::String(coll) => self.check(node, Access::Write, dbname, coll)?,
+          _ => {
+            let into = if op == "$out" { arg } else { &arg["into"] };
+            let db = into["db"].as_str().unwrap_or(dbname);
+            let coll = into["coll"].as_str().or(into.as_str())
+              .ok_or_else(|| ApiError::new(ErrorKind::InvalidInput, format!("{} has no target collection for {}", node, op)))?;
+            self.check(node, Access::Write, db, coll)?;
+          }
+        },
+        _ => {},
+      }
+    }
+    Ok(())
+  }
+}
+
+/// The stages of a pipeline along with those of the sub-pipelines of `$facet`,
+/// `$lookup` and `$unionWith`
+fn all_stages(pipeline: &[Value]) -> Vec<(&str, &Value)> {
+  let mut stages = vec![];
+  for stage in pipeline {
+    for (op, arg) in stage.as_object().into_iter().flatten() {
+      stages.push((op.as_str(), arg));
+      let nested: Vec<&Value> = match op.as_str() {
+        "$facet" => arg.as_object().into_iter().flatten().map(|(_, p)| p).collect(),
+        "$lookup" | "$unionWith" => vec![&arg["pipeline"]],
+        _ => vec![],
+      };
+      for sub in nested.into_iter().filter_map(Value::as_array) {
+        stages.extend(all_stages(sub));
+      }
+    }
+  }
+  stages
+}
+
+/// The tenant a flow runs for. Every document the Mongo nodes read or write
+/// carries a `tenant` field, documents without one belong to untenanted callers.
+#[derive(Debug, Clone, Default)]
+pub struct Tenant(pub Option<String>);
+
+impl Tenant {
+  /// Restricts a query to the tenant's documents
+  pub fn filter(&self, mut query: Document) -> Document {
+    query.insert("tenant", bson_or_null(self.0.clone()));
+    query
+  }
+
+  /// Marks a document that is about to be inserted or replaced as the tenant's
+  pub fn stamp(&self, doc: &mut Document) {
+    doc.insert("tenant", bson_or_null(self.0.clone()));
+  }
+
+  /// Update operators may not move documents to another tenant
+  pub fn check_update(&self, node: &str, update: &Document) -> Result<()> {
+    let is_tenant = |field: &str| field == "tenant" || field.starts_with("tenant.");
+    let touches_tenant = update.iter().any(|(op, fields)| match fields {
+      // `$rename` names the field it writes to in its values
+      Bson::Document(d) => d.iter().any(|(k, v)| is_tenant(k.as_str()) || (op == "$rename" && v.as_str().map_or(false, is_tenant))),
+      _ => false,
+    });
+    if touches_tenant {
+      return Err(ApiError::new(ErrorKind::Forbidden, format!("{} is not allowed to update the tenant field", node)).into());
+    }
+    Ok(())
+  }
+
+  /// Scopes an aggregation to the tenant's documents, or the untenanted ones.
+  /// Stages that pull in other collections or write anywhere would bypass that,
+  /// so they can't be used, not even in sub-pipelines.
+  pub fn pipeline(&self, node: &str, mut pipeline: Vec<Document>) -> Result<Vec<Document>> {
+    let stages: Vec<Value> = pipeline.iter().map(|d| Bson::Document(d.clone()).into_relaxed_extjson()).collect();
+    if let Some((op, _)) = all_stages(&stages).into_iter().find(|(op, _)| TENANT_FORBIDDEN.contains(op)) {
+      return Err(ApiError::new(ErrorKind::Forbidden, format!("{} is not allowed to use {}, it would reach past the tenant's documents", node, op)).into());
+    }
+    pipeline.insert(0, doc!{"$match": {"tenant": bson_or_null(self.0.clone())}});
+    Ok(pipeline)
+  }
+}
+
+#[cfg(test)]
+mod policy_test {
+  use crate::nocode::policy::{Access, MongoPolicy, Tenant};
+
+  #[test]
+  fn default_hides_flows() {
+    let policy = MongoPolicy::default();
+    assert!(policy.allows(Access::Read, "flows", "cache"));
+    assert!(!policy.allows(Access::Read, "flows", "flowsmeta"));
+    assert!(!policy.allows(Access::Write, "other", "cache"));
+  }
+
+  #[test]
+  fn checks_pipelines() {
+    let policy: MongoPolicy = serde_json::from_value(json!({"read": {"app": ["orders", "users"]}})).unwrap();
+    assert!(policy.check_pipeline("test", "app", &[json!({"$lookup": {"from": "users"}})]).is_ok());
+    assert!(policy.check_pipeline("test", "app", &[json!({"$lookup": {"from": "secrets"}})]).is_err());
+    assert!(policy.check_pipeline("test", "app", &[json!({"$out": "orders"})]).is_err());
+    assert!(policy.check_pipeline("test", "app", &[json!({"$facet": {"x": [{"$lookup": {"from": "secrets"}}]}})]).is_err());
+    assert!(policy.check_pipeline("test", "app", &[json!({"$lookup": {"from": "users", "pipeline": [{"$unionWith": "flowsmeta"}]}})]).is_err());
+    let wildcard: MongoPolicy = serde_json::from_value(json!({"write": {"app": ["*"]}})).unwrap();
+    assert!(wildcard.check_pipeline("test", "app", &[json!({"$merge": {"into": {"db": "app"}}})]).is_err());
+  }
+
+  #[test]
+  fn scopes_tenants() {
+    let tenant = Tenant(Some("acme".to_string()));
+    assert_eq!(tenant.filter(doc!{"name": "a"}), doc!{"name": "a", "tenant": "acme"});
+    assert!(tenant.check_update("test", &doc!{"$set": {"tenant": "other"}}).is_err());
+    assert!(tenant.check_update("test", &doc!{"$set": {"name": "b"}}).is_ok());
+    assert!(tenant.check_update("test", &doc!{"$rename": {"owner": "tenant"}}).is_err());
+    assert!(tenant.check_update("test", &doc!{"$rename": {"tenant": "owner"}}).is_err());
+    assert!(tenant.pipeline("test", vec![doc!{"$lookup": {"from": "users"}}]).is_err());
+    assert!(tenant.pipeline("test", vec![doc!{"$facet": {"x": [{"$unionWith": "users"}]}}]).is_err());
+    assert!(tenant.pipeline("test", vec![doc!{"$addFields": {"tenant": "other"}}, doc!{"$merge": {"into": "orders"}}]).is_err());
+    assert_eq!(Tenant(None).pipeline("test", vec![]).unwrap(), vec![doc!{"$match": {"tenant": null}}]);
+    assert!(Tenant(None).pipeline("test", vec![doc!{"$lookup": {"from": "users"}}]).is_err());
+    assert!(Tenant(None).pipeline("test", vec![doc!{"$out": "orders"}]).is_err());
+  }
+}
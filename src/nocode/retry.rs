@@ -0,0 +1,159 @@
+use std::env;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::convert::TryFrom;
+use std::time::Duration;
+use d3ne::Node;
+use serde_json::Value;
+
+use crate::nocode::error::{ApiError, ErrorKind};
+
+/// Attempts above this are not honoured, whatever the node asks for
+const MAX_ATTEMPTS: u32 = 10;
+/// The longest wait between two attempts
+const MAX_BACKOFF_MS: u64 = 30_000;
+/// Nodes another attempt could repeat writes for, after a failure that came too late
+const NOT_IDEMPOTENT: [&str; 3] = ["MongoDB Insert", "Call Flow", "ForEach"];
+
+/// How often a node is run again when it fails, set under `retry` in the node data,
+/// e.g. `{"max_attempts": 3, "backoff_ms": 200, "retry_on": ["database"]}`.
+/// The wait doubles after each attempt.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryPolicy {
+  #[serde(default = "default_attempts")]
+  pub max_attempts: u32,
+  #[serde(default = "default_backoff")]
+  pub backoff_ms: u64,
+  /// the kinds of errors worth another attempt, transient ones by default
+  #[serde(default = "default_kinds")]
+  pub retry_on: Vec<ErrorKind>,
+}
+
+fn default_attempts() -> u32 {
+  3
+}
+
+fn default_backoff() -> u64 {
+  100
+}
+
+fn default_kinds() -> Vec<ErrorKind> {
+  vec![ErrorKind::Database, ErrorKind::Unavailable]
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    RetryPolicy { max_attempts: 1, backoff_ms: 0, retry_on: vec![] }
+  }
+}
+
+impl RetryPolicy {
+  /// The policy of `node`, a single attempt when it sets none
+  pub fn of(node: &Node) -> Result<Self, ApiError> {
+    match node.data.as_ref().map(|d| &d["retry"]) {
+      None | Some(Value::Null) => Ok(RetryPolicy::default()),
+      Some(retry) => RetryPolicy::for_node(&node.name, retry),
+    }
+  }
+
+  /// Parses the policy a node of type `name` sets, which nodes that aren't safe to run twice can't
+  pub fn for_node(name: &str, retry: &Value) -> Result<Self, ApiError> {
+    if NOT_IDEMPOTENT.contains(&name) {
+      return Err(ApiError::new(ErrorKind::InvalidInput, format!("{} nodes can't be retried, another attempt could repeat their writes", name)).input("retry"));
+    }
+    RetryPolicy::parse(retry)
+  }
+
+  pub fn parse(retry: &Value) -> Result<Self, ApiError> {
+    let policy: RetryPolicy = serde_json::from_value(retry.clone())
+      .map_err(|e| ApiError::new(ErrorKind::InvalidInput, format!("invalid retry policy: {}", e)).input("retry"))?;
+    if !(1..=MAX_ATTEMPTS).contains(&policy.max_attempts) {
+      return Err(ApiError::new(ErrorKind::InvalidInput, format!("max_attempts must be between 1 and {}", MAX_ATTEMPTS)).input("retry"));
+    }
+    Ok(policy)
+  }
+
+  /// Whether `error` on attempt number `attempt` (from 1) is worth another one
+  pub fn retries(&self, error: &ApiError, attempt: u32) -> bool {
+    attempt < self.max_attempts && self.retry_on.contains(&error.kind)
+  }
+
+  /// How long to wait after attempt number `attempt` (from 1) failed
+  pub fn backoff(&self, attempt: u32) -> Duration {
+    let factor = 1u64.checked_shl(attempt.saturating_sub(1)).unwrap_or(u64::MAX);
+    Duration::from_millis(self.backoff_ms.saturating_mul(factor).min(MAX_BACKOFF_MS))
+  }
+}
+
+/// How long the retries of a run may wait in total, shared with the runs it
+/// starts through Call Flow and ForEach nodes. `MAX_RETRY_WAIT_MS` (default 10000)
+#[derive(Debug, Clone)]
+pub struct RetryBudget(Arc<AtomicU64>);
+
+impl RetryBudget {
+  pub fn new(ms: u64) -> Self {
+    RetryBudget(Arc::new(AtomicU64::new(ms)))
+  }
+
+  /// Takes `wait` out of the budget, false when not enough of it is left
+  pub fn take(&self, wait: Duration) -> bool {
+    let ms = u64::try_from(wait.as_millis()).unwrap_or(u64::MAX);
+    self.0.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(ms)).is_ok()
+  }
+}
+
+impl Default for RetryBudget {
+  fn default() -> Self {
+    RetryBudget::new(env::var("MAX_RETRY_WAIT_MS").ok().and_then(|s| s.parse().ok()).unwrap_or(10_000))
+  }
+}
+
+#[cfg(test)]
+mod retry_test {
+  use std::time::Duration;
+  use crate::nocode::error::{ApiError, ErrorKind};
+  use crate::nocode::retry::{RetryBudget, RetryPolicy};
+
+  #[test]
+  fn parses_policies() {
+    let policy = RetryPolicy::parse(&json!({"max_attempts": 4})).unwrap();
+    assert_eq!(policy.retry_on, vec![ErrorKind::Database, ErrorKind::Unavailable]);
+    assert_eq!(RetryPolicy::parse(&json!({"retry_on": ["node"]})).unwrap().max_attempts, 3);
+    assert!(RetryPolicy::parse(&json!({"max_attempts": 0})).is_err());
+    assert!(RetryPolicy::parse(&json!({"max_attempts": 11})).is_err());
+    assert!(RetryPolicy::parse(&json!({"retry_on": ["sometimes"]})).is_err());
+    assert!(RetryPolicy::parse(&json!({"attempts": 2})).is_err());
+    assert!(RetryPolicy::for_node("MongoDB Get", &json!({})).is_ok());
+    assert!(RetryPolicy::for_node("MongoDB Insert", &json!({})).is_err());
+    assert!(RetryPolicy::for_node("Call Flow", &json!({})).is_err());
+  }
+
+  #[test]
+  fn retries_listed_kinds() {
+    let policy = RetryPolicy::parse(&json!({"max_attempts": 3})).unwrap();
+    let transient = ApiError::new(ErrorKind::Database, "connection reset");
+    assert!(policy.retries(&transient, 1));
+    assert!(policy.retries(&transient, 2));
+    assert!(!policy.retries(&transient, 3));
+    assert!(!policy.retries(&ApiError::new(ErrorKind::Forbidden, "not allowed"), 1));
+    assert!(!RetryPolicy::default().retries(&transient, 1));
+  }
+
+  #[test]
+  fn backs_off() {
+    let policy = RetryPolicy::parse(&json!({"backoff_ms": 100})).unwrap();
+    assert_eq!(policy.backoff(1), Duration::from_millis(100));
+    assert_eq!(policy.backoff(3), Duration::from_millis(400));
+    assert_eq!(policy.backoff(64), Duration::from_millis(30_000));
+  }
+
+  #[test]
+  fn spends_budget() {
+    let budget = RetryBudget::new(250);
+    let shared = budget.clone();
+    assert!(budget.take(Duration::from_millis(200)));
+    assert!(!shared.take(Duration::from_millis(100)));
+    assert!(shared.take(Duration::from_millis(50)));
+  }
+}
@@ -0,0 +1,267 @@
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use bson::Document;
+use mongodb::sync::Client;
+use rocket::http::Status;
+use serde_json::Value;
+
+use crate::apikey::{check_access, ApiKey};
+use crate::nocode::{allows_anonymous, process_flow, registry, run_output, scoped, Kind, NoCodeError};
+use crate::nocode::acl::{self, Acl, Role};
+use crate::nocode::error::{ApiError, ErrorKind};
+use crate::nocode::flow::FlowContext;
+use crate::nocode::policy::{MongoPolicy, Tenant};
+use crate::nocode::retry::RetryBudget;
+
+/// Runs graphs from inside a worker, the nested graph of a ForEach or a stored
+/// flow, with the same nodes, policy and tenant as the run that started them.
+/// Owns its connection so element runs can be spread over threads.
+#[derive(Clone)]
+pub struct SubFlows {
+  pub conn: Client,
+  pub policy: MongoPolicy,
+  pub tenant: Tenant,
+  pub kind: Kind,
+  /// the api key of the run that started this one, none for anonymous runs
+  caller: Option<Arc<ApiKey>>,
+  /// the wait for retries left to this run and every run it starts
+  budget: RetryBudget,
+  /// how many runs this one is nested in
+  depth: usize,
+  /// the stored flows that called their way down to this run
+  calls: Vec<String>,
+}
+
+impl SubFlows {
+  pub fn new(conn: &Client, policy: &MongoPolicy, tenant: Tenant, kind: Kind, caller: Option<&ApiKey>) -> Self {
+    let caller = caller.map(|apikey| Arc::new(apikey.clone()));
+    SubFlows { conn: conn.clone(), policy: policy.clone(), tenant, kind, caller, budget: RetryBudget::default(), depth: 0, calls: vec![] }
+  }
+
+  /// The context of the run these sub-flows belong to
+  pub fn context(&self) -> FlowContext {
+    FlowContext::with_budget(self.budget.clone())
+  }
+
+  /// For the run of the stored flow `name`, so calls back into it are caught
+  pub fn within(mut self, name: &str) -> Self {
+    self.calls.push(name.to_string());
+    self
+  }
+
+  /// Runs `graph` with `payload` as its input, returning the status and payload of its Output node
+  pub fn run(&self, graph: Value, payload: Value) -> Result<(Status, Value), NoCodeError> {
+    if self.depth >= max_depth() {
+      return Err(NoCodeError::Recursion(format!("runs are nested deeper than {}", max_depth())));
+    }
+    let nested = SubFlows { depth: self.depth + 1, ..self.clone() };
+    let ctx = nested.context();
+    let output = process_flow(|| registry(&nested, payload.clone(), &ctx).engine(self.kind.engine), &ctx, graph)?;
+    run_output(&output)
+  }
+
+  /// Runs the stored flow `name` like `run_flow` would, at `rev` or its active revision
+  pub fn call(&self, name: &str, rev: Option<i64>, payload: Value) -> Result<(Status, Value), NoCodeError> {
+    let (graph, nested) = self.enter(name, rev)?;
+    nested.run(graph, payload)
+  }
+
+  /// Loads the stored flow `name` along with the sub-flows to run it with,
+  /// unless it is already calling its way down to this run
+  pub fn enter(&self, name: &str, rev: Option<i64>) -> Result<(Value, SubFlows), NoCodeError> {
+    if self.calls.iter().any(|c| c == name) {
+      return Err(NoCodeError::Recursion(format!("{} {} calls itself through {} -> {}", self.kind.graph, name, self.calls.join(" -> "), name)));
+    }
+    let graph = self.load(name, rev)?;
+    Ok((graph, self.clone().within(name)))
+  }
+
+  /// Runs `graph` once per element with `{"element": ..., "index": ...}` as its input.
+  /// With a `concurrency` above 1 elements run on that many threads, results come
+  /// back in element order when `ordered`, otherwise as they finish. Only the
+  /// outermost run spreads over threads, nested ForEach nodes run their elements in turn.
+  pub fn for_each(&self, graph: &Value, elements: Vec<Value>, concurrency: usize, ordered: bool) -> Result<Vec<Value>, NoCodeError> {
+    let run = |index: usize, element: Value| self.run(graph.clone(), json!({ "element": element, "index": index }))
+      .map(|(_, payload)| payload)
+      .map_err(|e| element_error(index, e));
+    if concurrency <= 1 || self.depth > 0 {
+      return elements.into_iter().enumerate().map(|(index, element)| run(index, element)).collect();
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let stop = Arc::new(AtomicBool::new(false));
+    let count = elements.len();
+    let mut lanes: Vec<Vec<(usize, Value)>> = vec![vec![]; concurrency.min(count)];
+    for (index, element) in elements.into_iter().enumerate() {
+      lanes[index % concurrency].push((index, element));
+    }
+    for lane in lanes {
+      let (sub, graph, sender, stop) = (self.clone(), graph.clone(), sender.clone(), stop.clone());
+      thread::spawn(move || {
+        for (index, element) in lane {
+          // once an element failed the others are not started
+          if stop.load(Ordering::SeqCst) {
+            return;
+          }
+          let result = sub.run(graph.clone(), json!({ "element": element, "index": index })).map(|(_, payload)| payload);
+          if result.is_err() {
+            stop.store(true, Ordering::SeqCst);
+          }
+          if sender.send((index, result)).is_err() {
+            return;
+          }
+        }
+      });
+    }
+    drop(sender);
+
+    // the receiver only runs dry once every lane is done, so nothing keeps
+    // running after the flow responded
+    let mut results = Vec::with_capacity(count);
+    let mut failed = None;
+    for (index, result) in receiver {
+      match result {
+        Ok(value) => results.push((index, value)),
+        Err(e) => if failed.is_none() {
+          failed = Some(element_error(index, e));
+        },
+      }
+    }
+    if let Some(e) = failed {
+      return Err(e);
+    }
+    if results.len() < count {
+      return Err(NoCodeError::Run(ApiError::new(ErrorKind::Internal, "an element run stopped without a result")));
+    }
+    if ordered {
+      results.sort_by_key(|(index, _)| *index);
+    }
+    Ok(results.into_iter().map(|(_, value)| value).collect())
+  }
+
+  /// The graph of a stored flow, its active revision unless `rev` pins one.
+  /// The caller needs to be allowed to run the flow like with `run_flow`.
+  pub fn load(&self, name: &str, rev: Option<i64>) -> Result<Value, NoCodeError> {
+    let db = self.conn.database(self.kind.name);
+    let meta = db.collection(self.kind.meta).find_one(scoped(doc!("name": name), &self.tenant.0), None)?
+      .ok_or_else(|| NoCodeError::NotFound(format!("{} {} does not exist", self.kind.graph, name)))?;
+    if !self.may_run(name, &meta) {
+      return Err(NoCodeError::Run(ApiError::new(ErrorKind::Forbidden, format!("not allowed to run {} {}", self.kind.graph, name))));
+    }
+    let rev = rev.unwrap_or_else(|| meta.get_i64("active_rev").unwrap_or(1i64));
+    let entry = db.collection(self.kind.name).find_one(scoped(doc!("name": name, "rev": rev), &self.tenant.0), None)?
+      .ok_or_else(|| NoCodeError::NotFound(format!("revision {} of {} {} does not exist", rev, self.kind.graph, name)))?;
+    match entry.get(self.kind.graph) {
+      Some(graph) => Ok(graph.clone().into_relaxed_extjson()),
+      None => Err(NoCodeError::InvalidGraph(format!("{} {} has no graph", self.kind.graph, name))),
+    }
+  }
+
+  /// Anonymous runs may only call flows that are open to anonymous callers themselves
+  fn may_run(&self, name: &str, meta: &Document) -> bool {
+    match &self.caller {
+      Some(apikey) => check_access(apikey, self.kind.name, "run")
+        && acl::allowed(self.kind.name, Acl::from_document(meta), apikey, Role::Runner),
+      None => allows_anonymous(self.kind, &self.tenant.0, name),
+    }
+  }
+}
+
+/// How deep runs may nest through Call Flow and ForEach nodes, `MAX_RUN_DEPTH` (default 8)
+fn max_depth() -> usize {
+  env::var("MAX_RUN_DEPTH").ok().and_then(|s| s.parse().ok()).unwrap_or(8)
+}
+
+fn element_error(index: usize, e: NoCodeError) -> NoCodeError {
+  let error = ApiError::from(e);
+  NoCodeError::Run(ApiError::new(error.kind, format!("element {}: {}", index, error)))
+}
+
+#[cfg(test)]
+mod subflow_test {
+  use mongodb::sync::Client;
+  use crate::apikey::ApiKey;
+  use crate::nocode::{NoCodeError, FLOWS};
+  use crate::nocode::error::ApiError;
+  use crate::nocode::policy::{MongoPolicy, Tenant};
+  use crate::nocode::subflow::SubFlows;
+  use crate::util::test::{apikey, passthrough};
+
+  fn subflows_for(caller: Option<&ApiKey>) -> SubFlows {
+    // nothing is read from the database, the client never connects
+    let conn = Client::with_uri_str("mongodb://localhost:27017").unwrap();
+    SubFlows::new(&conn, &MongoPolicy::default(), Tenant(None), FLOWS, caller)
+  }
+
+  fn subflows() -> SubFlows {
+    subflows_for(None)
+  }
+
+  fn runner(sub: &str) -> ApiKey {
+    apikey(sub, json!({ "scopes": [{ "scope": "flows", "actions": ["run"] }] }))
+  }
+
+  #[test]
+  fn runs_each_element() {
+    let graph = passthrough(200);
+    let elements = vec![json!("a"), json!("b"), json!("c")];
+    let expected = vec![
+      json!({"element": "a", "index": 0}),
+      json!({"element": "b", "index": 1}),
+      json!({"element": "c", "index": 2}),
+    ];
+    let sub = subflows();
+    assert_eq!(sub.for_each(&graph, elements.clone(), 1, true).unwrap(), expected);
+    assert_eq!(sub.for_each(&graph, elements.clone(), 2, true).unwrap(), expected);
+    assert_eq!(sub.for_each(&graph, elements.clone(), 2, false).unwrap().len(), 3);
+    let mut nested = subflows();
+    nested.depth = 1;
+    assert_eq!(nested.for_each(&graph, elements, 2, true).unwrap(), expected);
+  }
+
+  #[test]
+  fn stops_cycles_and_deep_nesting() {
+    let sub = subflows().within("a").within("b");
+    match sub.call("a", None, json!({})) {
+      Err(NoCodeError::Recursion(message)) => assert!(message.contains("a -> b -> a"), "{}", message),
+      _ => panic!("expected a call cycle"),
+    }
+    let mut nested = subflows();
+    nested.depth = 8;
+    assert!(matches!(nested.run(json!({}), json!({})), Err(NoCodeError::Recursion(_))));
+  }
+
+  #[test]
+  fn stops_for_each_cycles() {
+    let graph = json!({"id": "flows@1.0.0", "nodes": {
+      "1": { "id": 1, "name": "Input", "data": {}, "inputs": {}, "position": [0, 0],
+        "outputs": { "payload": { "connections": [{ "node": 2, "input": "payload", "data": {} }] } } },
+      "2": { "id": 2, "name": "ForEach", "data": { "name": "a" }, "position": [0, 0],
+        "inputs": { "payload": { "connections": [{ "node": 1, "output": "payload", "data": {} }] } },
+        "outputs": { "json": { "connections": [{ "node": 3, "input": "payload", "data": {} }] } } },
+      "3": { "id": 3, "name": "Output", "data": { "status": 200 }, "outputs": {}, "position": [0, 0],
+        "inputs": { "payload": { "connections": [{ "node": 2, "output": "json", "data": {} }] } } }
+    }});
+    let error = ApiError::from(subflows().within("a").run(graph, json!([1])).unwrap_err());
+    assert!(error.message.contains("calls itself"), "{}", error.message);
+  }
+
+  #[test]
+  fn reports_failing_element() {
+    let graph = json!({"id": "flows@1.0.0", "nodes": {}});
+    assert!(subflows().for_each(&graph, vec![json!(1)], 1, true).is_err());
+    assert!(subflows().for_each(&graph, vec![json!(1), json!(2), json!(3)], 2, true).is_err());
+  }
+
+  #[test]
+  fn checks_callee_acl() {
+    let meta = doc!{"name": "private", "acl": {"owners": ["alice"], "editors": [], "runners": ["bob"]}};
+    assert!(subflows_for(Some(&runner("bob"))).may_run("private", &meta));
+    assert!(!subflows_for(Some(&runner("mallory"))).may_run("private", &meta));
+    // anonymous runs only reach flows listed in ANONYMOUS_FLOWS
+    assert!(!subflows().may_run("private", &meta));
+  }
+}
@@ -0,0 +1,240 @@
+use std::collections::{BTreeMap, HashMap};
+use serde_json::Value;
+
+use crate::nocode::catalog::NodeSpec;
+use crate::nocode::retry::RetryPolicy;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+  Error,
+  Warning,
+}
+
+/// Something wrong with a graph, tied to the node the editor should highlight
+#[derive(Debug, Clone, Serialize)]
+pub struct Issue {
+  pub node: Option<i64>,
+  pub kind: &'static str,
+  pub severity: Severity,
+  pub message: String,
+}
+
+impl Issue {
+  fn error(node: Option<i64>, kind: &'static str, message: String) -> Self {
+    Issue { node, kind, severity: Severity::Error, message }
+  }
+
+  fn warning(node: Option<i64>, kind: &'static str, message: String) -> Self {
+    Issue { node, kind, severity: Severity::Warning, message }
+  }
+}
+
+pub fn has_errors(issues: &[Issue]) -> bool {
+  issues.iter().any(|i| i.severity == Severity::Error)
+}
+
+/// Checks a stored graph against the node catalog without running it
+pub fn validate(graph: &Value, specs: &[NodeSpec]) -> Vec<Issue> {
+  let nodes: BTreeMap<i64, &Value> = match graph["nodes"].as_object() {
+    Some(nodes) => nodes.iter()
+      .filter_map(|(key, node)| node["id"].as_i64().or_else(|| key.parse().ok()).map(|id| (id, node)))
+      .collect(),
+    None => return vec![Issue::error(None, "invalid_graph", "graph has no nodes".to_string())],
+  };
+  let catalog = specs;
+  let specs: HashMap<&str, &NodeSpec> = catalog.iter().map(|s| (s.name.as_str(), s)).collect();
+  let spec_of = |id: &i64| nodes.get(id).and_then(|n| n["name"].as_str()).and_then(|name| specs.get(name)).copied();
+  let mut issues = vec![];
+
+  for (id, node) in &nodes {
+    let name = node["name"].as_str().unwrap_or_default();
+    let spec = match specs.get(name) {
+      Some(spec) => *spec,
+      None => {
+        issues.push(Issue::error(Some(*id), "unknown_node", format!("unknown node type {:?}", name)));
+        continue;
+      }
+    };
+
+    for (key, input) in node["inputs"].as_object().into_iter().flatten() {
+      let socket = match spec.input_socket(key) {
+        Some(socket) => socket,
+        None => {
+          issues.push(Issue::error(Some(*id), "unknown_input", format!("{} has no input {}", name, key)));
+          continue;
+        }
+      };
+      for conn in input["connections"].as_array().into_iter().flatten() {
+        let from = conn["node"].as_i64().unwrap_or_default();
+        let output = conn["output"].as_str().unwrap_or_default();
+        match (nodes.contains_key(&from), spec_of(&from).map(|s| s.output_type(output, &nodes[&from]["data"]))) {
+          (false, _) | (true, Some(None)) => issues.push(Issue::error(Some(*id), "dangling_connection",
+            format!("input {} is connected to missing output {} of node {}", key, output, from))),
+          (true, Some(Some(out))) if !out.connects_to(socket.socket) => issues.push(Issue::error(Some(*id), "socket_mismatch",
+            format!("input {} expects {:?} but node {} output {} is {:?}", key, socket.socket, from, output, out))),
+          _ => {},
+        }
+      }
+    }
+
+    for (key, output) in node["outputs"].as_object().into_iter().flatten() {
+      if spec.output_type(key, &node["data"]).is_none() {
+        issues.push(Issue::error(Some(*id), "unknown_output", format!("{} has no output {}", name, key)));
+      }
+      for conn in output["connections"].as_array().into_iter().flatten() {
+        let to = conn["node"].as_i64().unwrap_or_default();
+        if !nodes.contains_key(&to) {
+          issues.push(Issue::error(Some(*id), "dangling_connection", format!("output {} is connected to missing node {}", key, to)));
+        }
+      }
+    }
+
+    if let Some(retry) = node["data"].get("retry").filter(|r| !r.is_null()) {
+      if let Err(e) = RetryPolicy::for_node(name, retry) {
+        issues.push(Issue::error(Some(*id), "invalid_retry", e.message));
+      }
+    }
+
+    // the graph a ForEach keeps inline runs with the same nodes, a stored flow named instead was checked when saved
+    if name == "ForEach" && node["data"]["name"].as_str().unwrap_or_default().is_empty() {
+      for issue in validate(&node["data"]["flow"], catalog) {
+        let at = issue.node.map(|n| format!(" node {}", n)).unwrap_or_default();
+        issues.push(Issue { node: Some(*id), message: format!("nested flow{}: {}", at, issue.message), ..issue });
+      }
+    }
+
+    for socket in spec.inputs.iter().filter(|s| s.required) {
+      let connected = node["inputs"][socket.key]["connections"].as_array().map(|c| !c.is_empty()).unwrap_or(false);
+      let set = !node["data"][socket.key].is_null();
+      if !connected && !set {
+        issues.push(Issue::error(Some(*id), "missing_input", format!("required input {} is not connected", socket.key)));
+      }
+    }
+  }
+
+  for id in cycle(&nodes) {
+    issues.push(Issue::error(Some(id), "cycle", "node is part of a cycle".to_string()));
+  }
+
+  for &(name, kind) in &[("Input", "input_node"), ("Output", "output_node")] {
+    let found: Vec<i64> = nodes.iter().filter(|(_, n)| n["name"] == name).map(|(id, _)| *id).collect();
+    match found.len() {
+      0 => issues.push(Issue::error(None, kind, format!("graph has no {} node", name))),
+      1 => {},
+      // several outputs are fine when they sit on different branches
+      _ if name == "Output" => issues.extend(found.iter().map(|id| Issue::warning(Some(*id), kind, format!("graph has {} Output nodes", found.len())))),
+      _ => issues.extend(found.iter().map(|id| Issue::error(Some(*id), kind, format!("graph has {} Input nodes", found.len())))),
+    }
+  }
+  issues
+}
+
+/// The nodes of the first cycle found following output connections
+fn cycle(nodes: &BTreeMap<i64, &Value>) -> Vec<i64> {
+  let edges: HashMap<i64, Vec<i64>> = nodes.iter().map(|(id, node)| {
+    let targets = node["outputs"].as_object().into_iter().flatten()
+      .flat_map(|(_, o)| o["connections"].as_array().into_iter().flatten())
+      .filter_map(|c| c["node"].as_i64())
+      .filter(|to| nodes.contains_key(to))
+      .collect();
+    (*id, targets)
+  }).collect();
+
+  // 1 while on the current path, 2 once all its successors are done
+  let mut state: HashMap<i64, u8> = HashMap::new();
+  for start in nodes.keys() {
+    if state.contains_key(start) {
+      continue;
+    }
+    let mut path: Vec<(i64, usize)> = vec![(*start, 0)];
+    state.insert(*start, 1);
+    while let Some((id, next)) = path.last().cloned() {
+      match edges[&id].get(next) {
+        Some(to) => {
+          if let Some(p) = path.last_mut() {
+            p.1 += 1;
+          }
+          match state.get(to) {
+            Some(1) => {
+              let from = path.iter().position(|(n, _)| n == to).unwrap_or_default();
+              return path[from..].iter().map(|(n, _)| *n).collect();
+            },
+            Some(_) => {},
+            None => {
+              state.insert(*to, 1);
+              path.push((*to, 0));
+            },
+          }
+        },
+        None => {
+          state.insert(id, 2);
+          path.pop();
+        },
+      }
+    }
+  }
+  vec![]
+}
+
+#[cfg(test)]
+mod validate_test {
+  use crate::nocode::catalog::{NodeSpec, SocketType};
+  use crate::nocode::validate::{has_errors, validate};
+
+  fn specs() -> Vec<NodeSpec> {
+    vec![
+      NodeSpec::new("Input").output("payload", SocketType::Json),
+      NodeSpec::new("Output").input("payload", SocketType::Json).input("status", SocketType::Number).control("status", json!(200)),
+      NodeSpec::new("Number").control("num", json!(0)).output("num", SocketType::Number),
+      NodeSpec::new("Add").input("num", SocketType::Number).input("num2", SocketType::Number).output("num", SocketType::Number),
+      NodeSpec::new("ForEach").optional("payload", SocketType::Json).control("flow", json!({})).control("name", json!("")).output("json", SocketType::Json),
+    ]
+  }
+
+  fn kinds(graph: serde_json::Value) -> Vec<&'static str> {
+    validate(&graph, &specs()).iter().map(|i| i.kind).collect()
+  }
+
+  #[test]
+  fn valid_graph() {
+    let graph = json!({"nodes": {
+      "1": { "id": 1, "name": "Input", "data": {}, "inputs": {}, "outputs": { "payload": { "connections": [{ "node": 2, "input": "payload" }] } } },
+      "2": { "id": 2, "name": "Output", "data": { "status": 200 }, "inputs": { "payload": { "connections": [{ "node": 1, "output": "payload" }] } }, "outputs": {} }
+    }});
+    assert!(!has_errors(&validate(&graph, &specs())));
+  }
+
+  #[test]
+  fn reports_issues() {
+    let graph = json!({"nodes": {
+      "1": { "id": 1, "name": "Number", "data": { "num": 1 }, "inputs": {}, "outputs": { "num": { "connections": [{ "node": 2, "input": "payload" }, { "node": 9, "input": "num" }] } } },
+      "2": { "id": 2, "name": "Output", "data": {}, "inputs": { "payload": { "connections": [{ "node": 1, "output": "num" }] } }, "outputs": {} },
+      "3": { "id": 3, "name": "Subtract", "data": {}, "inputs": {}, "outputs": {} },
+      "4": { "id": 4, "name": "Add", "data": { "retry": { "max_attempts": 0 } }, "inputs": { "num": { "connections": [{ "node": 4, "output": "num" }] } }, "outputs": { "num": { "connections": [{ "node": 4, "input": "num" }] } } }
+    }});
+    let found = kinds(graph);
+    for kind in &["socket_mismatch", "dangling_connection", "unknown_node", "missing_input", "cycle", "input_node", "invalid_retry"] {
+      assert!(found.contains(kind), "{} not in {:?}", kind, found);
+    }
+  }
+
+  #[test]
+  fn checks_nested_flows() {
+    let nested = json!({"nodes": {
+      "1": { "id": 1, "name": "Input", "data": {}, "inputs": {}, "outputs": {} },
+      "2": { "id": 2, "name": "Subtract", "data": {}, "inputs": {}, "outputs": {} }
+    }});
+    let graph = |data| json!({"nodes": {
+      "1": { "id": 1, "name": "Input", "data": {}, "inputs": {}, "outputs": {} },
+      "2": { "id": 2, "name": "ForEach", "data": data, "inputs": {}, "outputs": {} },
+      "3": { "id": 3, "name": "Output", "data": { "status": 200 }, "inputs": {}, "outputs": {} }
+    }});
+    let issues = validate(&graph(json!({ "flow": nested })), &specs());
+    let kinds: Vec<_> = issues.iter().filter(|i| i.node == Some(2)).map(|i| i.kind).collect();
+    assert_eq!(kinds, vec!["unknown_node", "output_node"]);
+    assert!(issues.iter().any(|i| i.message == "nested flow node 2: unknown node type \"Subtract\""));
+    // a stored flow is checked when it is saved
+    assert!(validate(&graph(json!({ "flow": {}, "name": "each" })), &specs()).iter().all(|i| i.node != Some(2)));
+  }
+}
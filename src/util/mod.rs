@@ -1,9 +1,11 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 use rocket_contrib::json::JsonValue;
-use rocket::http::Status;
 use rocket::response::status;
 
+use crate::nocode::error::{ApiError, ErrorKind};
 
+#[cfg(test)]
+pub mod test;
 
 pub fn current_millis() -> Result<i64, std::time::SystemTimeError> {
   let now = SystemTime::now();
@@ -17,13 +19,23 @@ pub fn to_bson_owned<A>(a: &A) -> Result<bson::Document, bson::ser::Error> where
   Ok(doc.to_owned())
 }
 
+/// `value` as bson, `None` as null
+pub fn bson_or_null<T: Into<bson::Bson>>(value: Option<T>) -> bson::Bson {
+  value.map_or(bson::Bson::Null, Into::into)
+}
+
 pub fn unauthorized() -> status::Custom<JsonValue> {
-  status::Custom(Status::Unauthorized, json!([{"errors": [{"code":5, "message": "does not have access to this call"}]}]).into())
+  ApiError::new(ErrorKind::Unauthorized, "does not have access to this call").into()
+}
+
+#[catch(401)]
+pub fn unauthorized_catcher() -> status::Custom<JsonValue> {
+  unauthorized()
 }
 
 #[catch(404)]
 pub fn not_found_catcher() -> status::Custom<JsonValue> {
-  status::Custom(Status::NotFound, json!([{"errors": [{"code":"1", "message": "not found"}]}]).into())
+  ApiError::new(ErrorKind::NotFound, "not found").into()
 }
 
 macro_rules! to_vec {
@@ -0,0 +1,22 @@
+use serde_json::Value;
+
+use crate::apikey::ApiKey;
+
+/// An api key for `sub` without scopes or entities, `claims` adds to or replaces its claims
+pub fn apikey(sub: &str, claims: Value) -> ApiKey {
+  let mut key = json!({ "iss": "test", "iat": 0, "jti": "1", "sub": sub, "legacy": false, "scopes": [], "entities": [] });
+  for (claim, value) in claims.as_object().into_iter().flatten() {
+    key[claim] = value.clone();
+  }
+  serde_json::from_value(key).unwrap()
+}
+
+/// A graph whose Input is passed on to an Output answering with `status`
+pub fn passthrough(status: i64) -> Value {
+  json!({"id": "flows@1.0.0", "nodes": {
+    "1": { "id": 1, "name": "Input", "data": {}, "inputs": {}, "position": [0, 0],
+      "outputs": { "payload": { "connections": [{ "node": 2, "input": "payload", "data": {} }] } } },
+    "2": { "id": 2, "name": "Output", "data": { "status": status }, "outputs": {}, "position": [0, 0],
+      "inputs": { "payload": { "connections": [{ "node": 1, "output": "payload", "data": {} }] } } }
+  }})
+}